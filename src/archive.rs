@@ -0,0 +1,130 @@
+//! Receipts bundled in zip or tar archives and directories.
+//!
+//! Archive members are read into memory, nothing is extracted to disk.
+
+use crate::errors::*;
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use fs_err as fs;
+
+/// File extensions of receipts which can be loaded.
+pub const RECEIPT_EXTENSIONS: &[&str] = &[
+    "pdf", "png", "jpeg", "jpg", "webp", "bmp", "tif", "tiff", "gif", "txt", "eml", "html", "htm",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn kind(path: &Path) -> Option<Kind> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".zip") {
+        Some(Kind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(Kind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(Kind::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Whether `path` names a zip or tar archive.
+pub fn is_archive(path: &Path) -> bool {
+    kind(path).is_some()
+}
+
+/// Whether `path` has the extension of a receipt which can be loaded.
+pub fn is_receipt(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| RECEIPT_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// All receipt files within the directory `dir`, sorted by name.
+pub fn directory_receipts(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut receipts = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && is_receipt(&path) {
+            receipts.push(path);
+        }
+    }
+    receipts.sort();
+    Ok(receipts)
+}
+
+/// All receipt members of the archive at `path`, sorted by name.
+pub fn archive_receipts(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut members = Vec::new();
+    match kind(path) {
+        Some(Kind::Zip) => {
+            let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+            for idx in 0..archive.len() {
+                let file = archive.by_index(idx)?;
+                if !file.is_dir() {
+                    members.push(PathBuf::from(file.name()));
+                }
+            }
+        }
+        Some(kind) => {
+            let mut archive = tar::Archive::new(tar_reader(path, kind)?);
+            for entry in archive.entries()? {
+                let entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    members.push(entry.path()?.into_owned());
+                }
+            }
+        }
+        None => bail!("{} is not a zip or tar archive", path.display()),
+    }
+    members.retain(|member| is_receipt(member));
+    members.sort();
+    Ok(members)
+}
+
+/// Read the content of `member` of the archive at `path`.
+pub fn read_member(path: &Path, member: &Path) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    match kind(path) {
+        Some(Kind::Zip) => {
+            let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+            let name = member.to_string_lossy();
+            archive
+                .by_name(&name)
+                .wrap_err_with(|| eyre!("{} has no member {}", path.display(), name))?
+                .read_to_end(&mut content)?;
+        }
+        Some(kind) => {
+            let mut archive = tar::Archive::new(tar_reader(path, kind)?);
+            let mut found = false;
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if entry.path()? == member {
+                    entry.read_to_end(&mut content)?;
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                bail!("{} has no member {}", path.display(), member.display());
+            }
+        }
+        None => bail!("{} is not a zip or tar archive", path.display()),
+    }
+    Ok(content)
+}
+
+fn tar_reader(path: &Path, kind: Kind) -> Result<Box<dyn Read>> {
+    let file = std::io::BufReader::new(fs::File::open(path)?);
+    Ok(if kind == Kind::TarGz {
+        Box::new(flate2::read::GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    })
+}
@@ -1,8 +1,18 @@
 use crate::errors::*;
+use crate::pdf::constants::PageSize;
+use crate::pdf::{
+    EncryptionAlgorithm, FooterPosition, ImageMode, ImageOptions, NUp, NUpLayout, PageFit, SignOff,
+    SignOffColumn, SignOffField,
+};
+use crate::sepa::PainVersion;
+
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
 
 use serde::Deserialize;
 
 use iban::Iban;
+use printpdf::Mm;
 
 use fs_err as fs;
 
@@ -13,6 +23,586 @@ pub struct CompanyConfig {
     pub address: String,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct LayoutConfig {
+    /// Stamp a link back to the summary onto every receipt page.
+    #[serde(default)]
+    pub back_links: bool,
+    /// Stamp the row number and reference onto every receipt page.
+    #[serde(default = "default_true")]
+    pub stamp_receipts: bool,
+    /// Precede the receipts of each record with a separation page.
+    #[serde(default)]
+    pub separation_pages: bool,
+    /// Page size of generated pages.
+    #[serde(default)]
+    pub page_size: PageSize,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            back_links: false,
+            stamp_receipts: true,
+            separation_pages: false,
+            page_size: PageSize::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ImagesConfig {
+    /// Downsample images to this resolution relative to their page.
+    #[serde(default = "default_max_dpi")]
+    pub max_dpi: f64,
+    /// Jpeg quality, by default only images which are not jpeg already
+    /// or are downsampled are encoded, with a quality of 80.
+    #[serde(default)]
+    pub quality: Option<u8>,
+    #[serde(default)]
+    pub mode: ImageMode,
+    /// Straighten photographed receipts.
+    #[serde(default)]
+    pub deskew: bool,
+    /// Crop photographed receipts to the paper.
+    #[serde(default)]
+    pub auto_crop: bool,
+    /// Place image receipts on pages of the configured page size.
+    #[serde(default)]
+    pub fit_to_page: bool,
+    /// Margin around images fitted to the page in mm.
+    #[serde(default = "default_image_margin")]
+    pub margin: f64,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        Self {
+            max_dpi: default_max_dpi(),
+            quality: None,
+            mode: ImageMode::default(),
+            deskew: false,
+            auto_crop: false,
+            fit_to_page: false,
+            margin: default_image_margin(),
+        }
+    }
+}
+
+impl ImagesConfig {
+    pub fn options(&self, page_size: PageSize) -> ImageOptions {
+        ImageOptions {
+            max_dpi: self.max_dpi,
+            quality: self.quality,
+            mode: self.mode,
+            deskew: self.deskew,
+            auto_crop: self.auto_crop,
+            fit: self.fit_to_page.then(|| PageFit {
+                dimensions: page_size.dimensions(),
+                margin: Mm(self.margin),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct NUpConfig {
+    /// Place image receipts several per page.
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub layout: NUpLayout,
+    /// Columns of the grid layout.
+    #[serde(default = "default_n_up_columns")]
+    pub columns: usize,
+    #[serde(default = "default_n_up_rows")]
+    pub rows: usize,
+    /// Margin of the shared pages in mm.
+    #[serde(default = "default_image_margin")]
+    pub margin: f64,
+    /// Place single page pdfs no larger than half a page as well.
+    #[serde(default)]
+    pub pdfs: bool,
+}
+
+impl Default for NUpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            layout: NUpLayout::default(),
+            columns: default_n_up_columns(),
+            rows: default_n_up_rows(),
+            margin: default_image_margin(),
+            pdfs: false,
+        }
+    }
+}
+
+impl NUpConfig {
+    pub fn n_up(&self, page_size: PageSize) -> Option<NUp> {
+        self.enabled.then(|| NUp {
+            layout: self.layout,
+            columns: self.columns,
+            rows: self.rows,
+            dimensions: page_size.dimensions(),
+            margin: Mm(self.margin),
+            pdfs: self.pdfs,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct FooterConfig {
+    /// Stamp page numbers, report identifier and employee name onto every page.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub position: FooterPosition,
+    #[serde(default = "default_footer_font_size")]
+    pub font_size: i32,
+    /// Overrides the report identifier, which defaults to the destination file name.
+    #[serde(default)]
+    pub report_id: Option<String>,
+}
+
+impl Default for FooterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            position: FooterPosition::default(),
+            font_size: default_footer_font_size(),
+            report_id: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AttachmentsConfig {
+    /// Embed the input csv file.
+    #[serde(default = "default_true")]
+    pub source: bool,
+    /// Embed a json summary of all rows and totals.
+    #[serde(default = "default_true")]
+    pub summary: bool,
+    /// Embed the original receipt files.
+    #[serde(default)]
+    pub receipts: bool,
+}
+
+impl Default for AttachmentsConfig {
+    fn default() -> Self {
+        Self {
+            source: true,
+            summary: true,
+            receipts: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MetadataConfig {
+    /// Overrides the document title, which defaults to one derived from the report identifier.
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Keywords in addition to the generated ones.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Additional fields of the document information dictionary.
+    #[serde(default)]
+    pub custom: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct EncryptionConfig {
+    #[serde(default)]
+    pub algorithm: EncryptionAlgorithm,
+    /// Command printing the password required to open the document.
+    #[serde(default)]
+    pub user_password_command: Option<String>,
+    /// Command printing the password required to lift the permission restrictions.
+    #[serde(default)]
+    pub owner_password_command: Option<String>,
+    #[serde(default = "default_true")]
+    pub allow_printing: bool,
+    #[serde(default)]
+    pub allow_modification: bool,
+    #[serde(default = "default_true")]
+    pub allow_copying: bool,
+    #[serde(default)]
+    pub allow_annotations: bool,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: EncryptionAlgorithm::default(),
+            user_password_command: None,
+            owner_password_command: None,
+            allow_printing: true,
+            allow_modification: false,
+            allow_copying: true,
+            allow_annotations: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SignOffConfig {
+    /// Render the sign-off block below the bank details.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Fields of each column, top to bottom.
+    #[serde(default = "default_sign_off_fields")]
+    pub fields: Vec<SignOffField>,
+    /// Number of approvers signing in addition to the employee.
+    #[serde(default = "default_approvers")]
+    pub approvers: usize,
+    /// Names pre-filled for the approvers, in order.
+    #[serde(default)]
+    pub approver_names: Vec<String>,
+    /// Pre-fill the name of the employee.
+    #[serde(default = "default_true")]
+    pub prefill_employee: bool,
+    #[serde(default)]
+    pub labels: SignOffLabels,
+}
+
+impl Default for SignOffConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            fields: default_sign_off_fields(),
+            approvers: default_approvers(),
+            approver_names: Vec::new(),
+            prefill_employee: true,
+            labels: SignOffLabels::default(),
+        }
+    }
+}
+
+impl SignOffConfig {
+    /// The sign-off block for `employee`, if enabled.
+    pub fn sign_off(&self, employee: &str) -> Option<SignOff> {
+        if !self.enabled {
+            return None;
+        }
+        let labels = &self.labels;
+        let approvers = self.approvers.max(self.approver_names.len());
+        let employee = SignOffColumn {
+            title: labels.employee.clone(),
+            name: Some(employee.to_owned()).filter(|_| self.prefill_employee),
+        };
+        let approvers = (0..approvers).map(|idx| SignOffColumn {
+            title: if approvers == 1 {
+                labels.approver.clone()
+            } else {
+                format!("{} {}", labels.approver, idx + 1)
+            },
+            name: self.approver_names.get(idx).cloned(),
+        });
+        let fields = self
+            .fields
+            .iter()
+            .map(|field| {
+                let label = match field {
+                    SignOffField::Name => &labels.name,
+                    SignOffField::Date => &labels.date,
+                    SignOffField::Signature => &labels.signature,
+                };
+                (*field, label.clone())
+            })
+            .collect();
+        Some(SignOff {
+            columns: std::iter::once(employee).chain(approvers).collect(),
+            fields,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct SignOffLabels {
+    pub employee: String,
+    pub approver: String,
+    pub name: String,
+    pub date: String,
+    pub signature: String,
+}
+
+impl Default for SignOffLabels {
+    fn default() -> Self {
+        Self {
+            employee: "Employee".to_owned(),
+            approver: "Approved by".to_owned(),
+            name: "Name".to_owned(),
+            date: "Date".to_owned(),
+            signature: "Signature".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SignatureConfig {
+    /// Digitally sign the generated document.
+    #[serde(default)]
+    pub enabled: bool,
+    /// PKCS#12 file containing the private key and certificate chain.
+    #[serde(default)]
+    pub pkcs12: Option<PathBuf>,
+    /// PEM encoded certificate, optionally followed by its chain, used instead of `pkcs12`.
+    #[serde(default)]
+    pub certificate: Option<PathBuf>,
+    /// PEM encoded private key of `certificate`.
+    #[serde(default)]
+    pub key: Option<PathBuf>,
+    /// Command printing the password of the PKCS#12 file or the private key.
+    #[serde(default)]
+    pub password_command: Option<String>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub contact: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GiroCodeConfig {
+    /// Show an EPC QR code with the credit transfer on the summary page.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Remittance information, defaults to the report identifier.
+    #[serde(default)]
+    pub reference: Option<String>,
+}
+
+impl Default for GiroCodeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            reference: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SepaConfig {
+    #[serde(default)]
+    pub version: PainVersion,
+    /// Account of the company, required to create a credit transfer.
+    #[serde(default)]
+    pub debtor: Option<DebtorConfig>,
+    /// Add to the transfers of an existing file instead of replacing it.
+    #[serde(default)]
+    pub batch: bool,
+    /// Remittance information, defaults to the report identifier.
+    #[serde(default)]
+    pub reference: Option<String>,
+    /// Requested execution date in days from today.
+    #[serde(default)]
+    pub execution_delay: u32,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DebtorConfig {
+    /// Defaults to the company name.
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(deserialize_with = "deserialize_iban")]
+    pub iban: Iban,
+    #[serde(default)]
+    pub bic: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReceiptsConfig {
+    /// Add a declaration page to be signed for every record without receipts.
+    #[serde(default)]
+    pub declaration_pages: bool,
+    /// Statement of the declaration pages.
+    #[serde(default)]
+    pub declaration: Option<String>,
+    /// List receipt files in the directory of the csv which no record refers to.
+    #[serde(default = "default_true")]
+    pub unreferenced: bool,
+}
+
+impl Default for ReceiptsConfig {
+    fn default() -> Self {
+        Self {
+            declaration_pages: false,
+            declaration: None,
+            unreferenced: true,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct HistoryConfig {
+    /// Remember the receipts of every report to flag them when submitted again.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Defaults to `history.json` in the data directory of the user.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// Fail instead of warning about receipts submitted with an earlier report
+    /// and similar looking images within a report.
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl HistoryConfig {
+    pub fn path(&self) -> Result<PathBuf> {
+        match self.path {
+            Some(ref path) => Ok(path.clone()),
+            None => crate::history::History::user_history_path(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct VerificationConfig {
+    /// Look for the amount of each record on the text of its pdf receipts.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Look for the date of each record as well.
+    #[serde(default = "default_true")]
+    pub dates: bool,
+    /// Fail instead of warning about values which could not be found.
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl Default for VerificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dates: true,
+            strict: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PasswordsConfig {
+    /// File with one password per line, tried for every encrypted receipt,
+    /// relative to the config file.
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    /// Passwords of encrypted receipts by glob pattern of their path.
+    #[serde(default)]
+    pub patterns: BTreeMap<String, String>,
+    /// Passwords of `file`, read once the first encrypted receipt is loaded.
+    #[serde(skip)]
+    file_passwords: OnceCell<Vec<String>>,
+}
+
+impl PasswordsConfig {
+    /// Passwords of `file`, if any.
+    fn file_passwords(&self) -> Result<&[String]> {
+        let file = if let Some(ref file) = self.file {
+            file
+        } else {
+            return Ok(&[]);
+        };
+        if let Some(passwords) = self.file_passwords.get() {
+            return Ok(passwords);
+        }
+        let content = fs::read_to_string(file)
+            .wrap_err_with(|| eyre!("Failed to read password file {}", file.display()))?;
+        let passwords = content
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(ToOwned::to_owned)
+            .collect();
+        Ok(self.file_passwords.get_or_init(|| passwords))
+    }
+
+    /// Candidate passwords for the receipt at `path`, the ones of matching
+    /// patterns first, followed by those of the password file.
+    pub fn for_receipt(&self, path: &Path) -> Result<Vec<String>> {
+        let full = path.to_string_lossy();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        let mut passwords = self
+            .patterns
+            .iter()
+            .filter(|(pattern, _)| glob_match(pattern, &full) || glob_match(pattern, &name))
+            .map(|(_, password)| password.clone())
+            .collect::<Vec<_>>();
+        passwords.extend(self.file_passwords()?.iter().cloned());
+        Ok(passwords)
+    }
+}
+
+/// Match `text` against a glob `pattern` with `*` and `?` wildcards.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and the text position it was tried at
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, tried)) => {
+                    p = star + 1;
+                    t = tried + 1;
+                    backtrack = Some((star, tried + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn default_sign_off_fields() -> Vec<SignOffField> {
+    vec![
+        SignOffField::Name,
+        SignOffField::Date,
+        SignOffField::Signature,
+    ]
+}
+
+fn default_approvers() -> usize {
+    1
+}
+
+fn default_max_dpi() -> f64 {
+    200.
+}
+
+fn default_image_margin() -> f64 {
+    10.
+}
+
+fn default_n_up_columns() -> usize {
+    2
+}
+
+fn default_n_up_rows() -> usize {
+    2
+}
+
+fn default_footer_font_size() -> i32 {
+    7
+}
+
+fn default_true() -> bool {
+    true
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     pub name: String,
@@ -22,6 +612,55 @@ pub struct Config {
 
     #[serde(default)]
     pub company: CompanyConfig,
+
+    #[serde(default)]
+    pub layout: LayoutConfig,
+
+    #[serde(default)]
+    pub images: ImagesConfig,
+
+    #[serde(default)]
+    pub n_up: NUpConfig,
+
+    #[serde(default)]
+    pub footer: FooterConfig,
+
+    #[serde(default)]
+    pub attachments: AttachmentsConfig,
+
+    #[serde(default)]
+    pub metadata: MetadataConfig,
+
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+
+    #[serde(default)]
+    pub sign_off: SignOffConfig,
+
+    #[serde(default)]
+    pub signature: SignatureConfig,
+
+    #[serde(default)]
+    pub girocode: GiroCodeConfig,
+
+    #[serde(default)]
+    pub sepa: SepaConfig,
+
+    #[serde(default)]
+    pub passwords: PasswordsConfig,
+
+    #[serde(default)]
+    pub receipts: ReceiptsConfig,
+
+    #[serde(default)]
+    pub verification: VerificationConfig,
+
+    #[serde(default)]
+    pub history: HistoryConfig,
+
+    /// Produce PDF/A-3b output.
+    #[serde(default)]
+    pub pdfa: bool,
 }
 
 use serde::de;
@@ -62,7 +701,14 @@ impl Config {
     pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
         let path = path.as_ref();
         let s = fs::read_to_string(path)?;
-        Self::load(&s)
+        let mut cfg = Self::load(&s)?;
+        // the password file is relative to the config file
+        if let (Some(file), Some(dir)) = (cfg.passwords.file.as_mut(), path.parent()) {
+            if file.is_relative() {
+                *file = dir.join(&file);
+            }
+        }
+        Ok(cfg)
     }
 
     pub fn load(s: &str) -> Result<Self> {
@@ -94,4 +740,83 @@ iban = "LI2308800847517261798"
 "#;
         let _ = Config::load(CFG).unwrap();
     }
+
+    #[test]
+    fn sign_off() {
+        static CFG: &str = r#"
+name = "Generated Garbage"
+iban = "LI2308800847517261798"
+
+[sign_off]
+approvers = 2
+approver_names = ["Jane"]
+fields = ["signature"]
+"#;
+        let config = Config::load(CFG).unwrap();
+        let sign_off = config.sign_off.sign_off(&config.name).unwrap();
+        let titles = sign_off
+            .columns
+            .iter()
+            .map(|column| column.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, vec!["Employee", "Approved by 1", "Approved by 2"]);
+        assert_eq!(
+            sign_off.columns[0].name.as_deref(),
+            Some("Generated Garbage")
+        );
+        assert_eq!(sign_off.columns[1].name.as_deref(), Some("Jane"));
+        assert_eq!(sign_off.columns[2].name, None);
+        assert_eq!(
+            sign_off.fields,
+            vec![(SignOffField::Signature, "Signature".to_owned())]
+        );
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("telekom_*.pdf", "telekom_2021_03.pdf"));
+        assert!(glob_match("*/flight-??.pdf", "receipts/flight-07.pdf"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("telekom_*.pdf", "telekom_2021_03.png"));
+        assert!(!glob_match("flight-?.pdf", "flight-07.pdf"));
+    }
+
+    #[test]
+    fn password_file() {
+        let dir = std::env::temp_dir().join(format!("shinypenny-passwords-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shinypenny.toml");
+        fs::write(
+            &path,
+            r#"
+name = "Generated Garbage"
+iban = "LI2308800847517261798"
+
+[passwords]
+file = "passwords"
+
+[passwords.patterns]
+"telekom_*.pdf" = "12345678"
+"#,
+        )
+        .unwrap();
+        // the file is only needed once a password is looked up
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.passwords.file, Some(dir.join("passwords")));
+        assert!(config
+            .passwords
+            .for_receipt(Path::new("telekom_03.pdf"))
+            .is_err());
+
+        fs::write(dir.join("passwords"), "# comment\nsecret\n").unwrap();
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(
+            config
+                .passwords
+                .for_receipt(Path::new("receipts/telekom_03.pdf"))
+                .unwrap(),
+            vec!["12345678".to_owned(), "secret".to_owned()]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
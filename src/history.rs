@@ -0,0 +1,174 @@
+//! Receipts of previous reports, to flag receipts which are submitted again.
+//!
+//! The history is a json file, the receipts of a run replace those of an
+//! earlier run writing the same report file with the same report id.
+
+use crate::errors::*;
+use crate::pdf::Fingerprint;
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use fs_err as fs;
+
+/// A receipt submitted with a report.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// Identifier of the report the receipt was submitted with.
+    pub report: String,
+    /// Date the report was created.
+    pub created: chrono::NaiveDate,
+    /// Path of the receipt, as given.
+    pub receipt: String,
+    pub fingerprint: Fingerprint,
+}
+
+/// An entry along with the run it was recorded by.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Recorded {
+    run: String,
+    #[serde(flatten)]
+    entry: Entry,
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    path: PathBuf,
+    entries: Vec<Recorded>,
+}
+
+/// Stable identifier of a run, given by the report file `dest` and its
+/// `report_id`, running a report again to the same file replaces it.
+pub fn run_key(dest: &Path, report_id: &str) -> Result<String> {
+    // the report itself does not exist before the first run
+    let dir = match dest.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file = dest
+        .file_name()
+        .ok_or_else(|| eyre!("Report path {} is not a file", dest.display()))?;
+    Ok(format!(
+        "{}#{}",
+        fs::canonicalize(dir)?.join(file).display(),
+        report_id
+    ))
+}
+
+impl History {
+    /// Default location in the data directory of the user.
+    pub fn user_history_path() -> Result<PathBuf> {
+        let dir = dirs::data_dir().ok_or_else(|| eyre!("Missing data dir for current user"))?;
+        Ok(dir.join("shinypenny").join("history.json"))
+    }
+
+    /// Load the history at `path`, which is empty if it does not exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        let entries = if path.exists() {
+            let s = fs::read_to_string(path)?;
+            serde_json::from_str(&s)
+                .wrap_err_with(|| eyre!("Failed to parse receipt history {}", path.display()))?
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            entries,
+        })
+    }
+
+    /// Earlier submissions of the receipt with `fingerprint` by runs other than `run`.
+    pub fn submissions<'a>(
+        &'a self,
+        fingerprint: &'a Fingerprint,
+        run: &'a str,
+    ) -> impl Iterator<Item = &'a Entry> {
+        self.entries
+            .iter()
+            .filter(move |recorded| {
+                recorded.run != run && recorded.entry.fingerprint.matches(fingerprint)
+            })
+            .map(|recorded| &recorded.entry)
+    }
+
+    /// Replace the receipts of `run` with `entries` and save the history.
+    pub fn record(&mut self, run: &str, entries: Vec<Entry>) -> Result<()> {
+        self.entries.retain(|recorded| recorded.run != run);
+        self.entries
+            .extend(entries.into_iter().map(|entry| Recorded {
+                run: run.to_owned(),
+                entry,
+            }));
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.entries)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resubmission() {
+        let dir = std::env::temp_dir().join(format!("shinypenny-history-{}", std::process::id()));
+        let path = dir.join("history.json");
+        let entry = |report: &str, content: &[u8]| Entry {
+            report: report.to_owned(),
+            created: chrono::NaiveDate::from_ymd(2021, 3, 4),
+            receipt: "taxi.pdf".to_owned(),
+            fingerprint: Fingerprint::new(content, None),
+        };
+
+        let mut history = History::load(&path).unwrap();
+        history
+            .record("2021-02", vec![entry("2021-02", b"taxi")])
+            .unwrap();
+        history
+            .record("2021-02", vec![entry("2021-02", b"taxi")])
+            .unwrap();
+
+        let history = History::load(&path).unwrap();
+        assert_eq!(history.entries.len(), 1);
+        let taxi = Fingerprint::new(b"taxi", None);
+        assert_eq!(history.submissions(&taxi, "2021-03").count(), 1);
+        // running a report again does not flag its own receipts
+        assert_eq!(history.submissions(&taxi, "2021-02").count(), 0);
+        assert_eq!(
+            history
+                .submissions(&Fingerprint::new(b"hotel", None), "2021-03")
+                .count(),
+            0
+        );
+
+        let march = run_key(&dir.join("2021-03.pdf"), "2021-03").unwrap();
+        assert!(march.ends_with("2021-03.pdf#2021-03"));
+        assert_ne!(march, run_key(&dir.join("2021-04.pdf"), "2021-03").unwrap());
+        assert!(run_key(Path::new("2021-03.pdf"), "2021-03").is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn next_month() {
+        let dir = std::env::temp_dir().join(format!("shinypenny-months-{}", std::process::id()));
+        let taxi = Fingerprint::new(b"taxi", None);
+        let entry = Entry {
+            report: "2021-03".to_owned(),
+            created: chrono::NaiveDate::from_ymd(2021, 3, 31),
+            receipt: "taxi.pdf".to_owned(),
+            fingerprint: taxi.clone(),
+        };
+        fs::create_dir_all(&dir).unwrap();
+        let march = run_key(&dir.join("2021-03.pdf"), "2021-03").unwrap();
+        let april = run_key(&dir.join("2021-04.pdf"), "2021-04").unwrap();
+
+        let mut history = History::load(&dir.join("history.json")).unwrap();
+        history.record(&march, vec![entry]).unwrap();
+        let earlier = history.submissions(&taxi, &april).collect::<Vec<_>>();
+        assert_eq!(earlier.len(), 1);
+        assert_eq!(earlier[0].report, "2021-03");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,5 +1,5 @@
 use std::io::BufRead;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use chrono::TimeZone;
 use docopt::Docopt;
@@ -19,12 +19,20 @@ mod pdf;
 mod config;
 use config::Config;
 
+mod sepa;
+
+mod archive;
+
+mod verify;
+
+mod history;
+
 const USAGE: &'static str = r#"
 shinypenny
 
 Usage:
-  shinypenny [(-q|-v...)] [-c <config>] [--learning] [--date=<date>] --company=<company> --desc=<desc> --brutto=<brutto> --tax-percent=<tax_percent> --netto=<netto> [--dest=<dest>] <receipts>..
-  shinypenny [(-q|-v...)] [-c <config>] [--learning] --csv=<csv> [--dest=<dest>]
+  shinypenny [(-q|-v...)] [-c <config>] [--learning] [--back-links] [--separation-pages] [--n-up] [--attach-receipts] [--pdfa] [--verify] [--user-password=<password>] [--owner-password=<password>] [--sign] [--sepa=<xml>] [--max-size=<size>] [--date=<date>] --company=<company> --desc=<desc> --brutto=<brutto> --tax-percent=<tax_percent> --netto=<netto> [--dest=<dest>] <receipts>..
+  shinypenny [(-q|-v...)] [-c <config>] [--learning] [--back-links] [--separation-pages] [--n-up] [--attach-receipts] [--pdfa] [--verify] [--user-password=<password>] [--owner-password=<password>] [--sign] [--sepa=<xml>] [--max-size=<size>] --csv=<csv> [--dest=<dest>]
   shinypenny config
   shinypenny --version
 
@@ -41,6 +49,17 @@ Options:
   --netto=<netto>               Value of the service goods without added tax.
   --date=<date>                 The date of receipt creation, defaults to today.
   --dest=<dest>                 Write the receipt to the given dest file
+  --back-links                  Stamp a link back to the summary onto every receipt page.
+  --separation-pages            Precede the receipts of each record with a separation page.
+  --n-up                        Place image receipts several per page.
+  --attach-receipts             Embed the original receipt files into the pdf.
+  --pdfa                        Produce archivable PDF/A-3b output.
+  --verify                      Look for the amount and date of each record on its pdf receipts.
+  --user-password=<password>    Encrypt the pdf, the password is required to open it.
+  --owner-password=<password>   Encrypt the pdf, the password is required to lift the restrictions.
+  --sign                        Digitally sign the pdf with the configured certificate.
+  --sepa=<xml>                  Write a SEPA credit transfer of the total for the configured debtor.
+  --max-size=<size>             Compress images harder until the pdf fits, i.e. `10M` or `500k`.
 "#;
 
 #[derive(Debug, Deserialize)]
@@ -58,6 +77,17 @@ struct Args {
     flag_verbose: Option<usize>,
     flag_quiet: bool,
     flag_learning: bool,
+    flag_back_links: bool,
+    flag_separation_pages: bool,
+    flag_n_up: bool,
+    flag_attach_receipts: bool,
+    flag_pdfa: bool,
+    flag_verify: bool,
+    flag_user_password: Option<String>,
+    flag_owner_password: Option<String>,
+    flag_sign: bool,
+    flag_sepa: Option<PathBuf>,
+    flag_max_size: Option<String>,
     flag_csv: Option<PathBuf>,
     flag_config: Option<PathBuf>,
 }
@@ -65,16 +95,206 @@ struct Args {
 use float_cmp::ApproxEq;
 use lopdf::Document;
 
-/// Create the pdf from all records
+use std::ops::Range;
+
+/// Receipt documents in order of their pages, small receipts are
+/// collected and placed several per page.
+struct ReceiptPages {
+    documents: Vec<Document>,
+    /// Number of pages of all documents.
+    count: usize,
+    /// Pages of each record.
+    records: Vec<Range<usize>>,
+    /// Pages holding the receipts of several records.
+    shared: Vec<usize>,
+    n_up: Option<pdf::NUp>,
+    /// Small receipts waiting to be placed, along with their record.
+    tiles: Vec<(usize, pdf::Tile)>,
+}
+
+impl ReceiptPages {
+    fn new(records: usize, n_up: Option<pdf::NUp>) -> Self {
+        Self {
+            documents: Vec::with_capacity(records + 1),
+            count: 0,
+            records: vec![0..0; records],
+            shared: Vec::new(),
+            n_up,
+            tiles: Vec::new(),
+        }
+    }
+
+    /// Add `pages` to the pages of `record`.
+    fn mark(&mut self, record: usize, pages: Range<usize>) {
+        let current = &mut self.records[record];
+        *current = if current.start == current.end {
+            pages
+        } else {
+            current.start.min(pages.start)..current.end.max(pages.end)
+        };
+    }
+
+    /// Append `document` to the pages of `record`, returns the index of its first page.
+    fn push(&mut self, record: usize, document: Document) -> Result<usize> {
+        self.flush()?;
+        let first = self.count;
+        self.count += document.get_pages().len();
+        self.mark(record, first..self.count);
+        self.documents.push(document);
+        Ok(first)
+    }
+
+    /// Reserve a single page for `record`, returns the index of the placeholder document.
+    fn reserve(&mut self, record: usize) -> Result<usize> {
+        self.flush()?;
+        self.mark(record, self.count..self.count + 1);
+        self.count += 1;
+        self.documents.push(Document::with_version("1.5"));
+        Ok(self.documents.len() - 1)
+    }
+
+    /// Append a receipt of `record`, returns the index of its first page
+    /// unless it is placed on a shared page later on.
+    fn push_receipt(
+        &mut self,
+        record: usize,
+        receipt: pdf::Receipt,
+        label: String,
+    ) -> Result<Option<usize>> {
+        match self.n_up {
+            Some(ref n_up) if n_up.accepts(&receipt) => {
+                let tile = pdf::Tile {
+                    label,
+                    document: receipt.document,
+                };
+                self.tiles.push((record, tile));
+                Ok(None)
+            }
+            _ => self.push(record, receipt.document).map(Some),
+        }
+    }
+
+    /// Place the collected small receipts onto shared pages.
+    fn flush(&mut self) -> Result<()> {
+        let n_up = match self.n_up {
+            Some(n_up) if !self.tiles.is_empty() => n_up,
+            _ => return Ok(()),
+        };
+        let (records, tiles): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.tiles).into_iter().unzip();
+        let (document, tile_pages) = n_up.compose(tiles)?;
+        let first = self.count;
+        for (record, page) in records.into_iter().zip(tile_pages) {
+            self.mark(record, (first + page)..(first + page + 1));
+        }
+        self.count += document.get_pages().len();
+        self.shared.extend(first..self.count);
+        self.documents.push(document);
+        Ok(())
+    }
+}
+
+/// Look for the values of `record` on the text of its pdf `receipts`.
+fn verify_receipts(
+    number: usize,
+    record: &Record,
+    receipts: &[pdf::Receipt],
+    config: &config::VerificationConfig,
+) -> Result<()> {
+    let pages = receipts
+        .iter()
+        .filter(|receipt| !receipt.image)
+        .flat_map(|receipt| pdf::page_texts(&receipt.document))
+        .collect::<Vec<_>>();
+    if pages.iter().all(|page| page.trim().is_empty()) {
+        log::info!(
+            "Receipts of row {} have no text, skipping verification",
+            number
+        );
+        return Ok(());
+    }
+    let missing = verify::missing_values(record, &pages, config.dates);
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let message = format!(
+        "Could not find the {} of row {} on its receipts",
+        missing.join(" and "),
+        number
+    );
+    if config.strict {
+        bail!(message);
+    }
+    log::warn!("{}", message);
+    Ok(())
+}
+
+/// Fail on a receipt of row `number` which was already submitted within the report,
+/// similar looking images are only warned about unless `strict`.
+fn check_duplicate(
+    number: usize,
+    entry: &history::Entry,
+    submitted: &[(usize, history::Entry)],
+    strict: bool,
+) -> Result<()> {
+    let duplicate = submitted
+        .iter()
+        .find(|(_, earlier)| earlier.fingerprint.matches(&entry.fingerprint));
+    if let Some((row, earlier)) = duplicate {
+        if earlier.fingerprint.identical(&entry.fingerprint) {
+            bail!(
+                "Receipt {} of row {} duplicates receipt {} of row {}",
+                entry.receipt,
+                number,
+                earlier.receipt,
+                row
+            );
+        }
+        let message = format!(
+            "Receipt {} of row {} looks like receipt {} of row {}",
+            entry.receipt, number, earlier.receipt, row
+        );
+        if strict {
+            bail!(message);
+        }
+        log::warn!("{}", message);
+    }
+    Ok(())
+}
+
+/// Warn about or fail on receipts which were submitted with an earlier report.
+fn check_history(
+    submitted: &[history::Entry],
+    history: &history::History,
+    run: &str,
+    strict: bool,
+) -> Result<()> {
+    for entry in submitted {
+        for earlier in history.submissions(&entry.fingerprint, run) {
+            let message = format!(
+                "Receipt {} was already submitted as {} with report {} on {}",
+                entry.receipt, earlier.receipt, earlier.report, earlier.created
+            );
+            if strict {
+                bail!(message);
+            }
+            log::warn!("{}", message);
+        }
+    }
+    Ok(())
+}
+
+/// Create the pdf from all records, along with the total to be reimbursed
+/// and the fingerprints of all receipts.
 fn create_pdf(
     records: &[Record],
     bankinfo: BankInfo,
     companyinfo: CompanyInfo,
+    config: &Config,
+    report_id: &str,
+    source: Option<&Path>,
     learning_budget: bool,
-) -> Result<Document> {
-    let separation_page = false;
-    let mut documents = Vec::with_capacity(records.len() + 1);
-
+) -> Result<(Document, Euro, Vec<history::Entry>)> {
     let mut rows = Vec::with_capacity(records.len());
     let mut totals = Totals::default();
 
@@ -82,11 +302,7 @@ fn create_pdf(
     let mut tax_percentage_set = indexmap::IndexSet::<Percentage>::default();
 
     // transform the csv `Record`s into table `Row` types
-    let mut receipts = Vec::with_capacity(32);
-
-    for record in records.into_iter() {
-        receipts.push((record.description.as_str(), &record.receipts));
-
+    for (idx, record) in records.iter().enumerate() {
         let mut brutto = record.brutto;
         let brutto_rate = brutto.exchange_rate();
 
@@ -145,6 +361,7 @@ fn create_pdf(
         tax_percentage_set.insert(percentage);
 
         let row = Row {
+            number: idx + 1,
             date: Date::from_utc(record.date, chrono::FixedOffset::west(0)), // TODO assume
             description: record.description.clone(),
             company: record.company.clone(),
@@ -172,27 +389,335 @@ fn create_pdf(
 
     log::info!("Number integrity checks and folding complete");
 
-    for (desc, receipt_paths) in receipts {
-        if separation_page {
-            documents.push(pdf::separation_page(desc)?);
+    // pages of each record, relative to the first receipt page
+    let mut pages = ReceiptPages::new(records.len(), config.n_up.n_up(config.layout.page_size));
+    let mut receipt_outlines = Vec::with_capacity(records.len());
+    let image_options = config.images.options(config.layout.page_size);
+    // receipts of all records so far, along with their row number
+    let mut submitted = Vec::<(usize, history::Entry)>::new();
+    let created = chrono::Local::today().naive_local();
+    let mut without_receipts = Vec::new();
+
+    for (idx, (record, row)) in records.iter().zip(rows.iter()).enumerate() {
+        let receipts = (&record.receipts)
+            .into_iter()
+            .map(|spec| {
+                let receipt = pdf::load_receipt(
+                    spec,
+                    &|path| config.passwords.for_receipt(path),
+                    &image_options,
+                    config.layout.page_size.dimensions(),
+                )?;
+                if config.pdfa {
+                    pdf::check_pdfa_compatible(&receipt.document).wrap_err_with(|| {
+                        eyre!("Receipt {} can not be made PDF/A conformant", spec)
+                    })?;
+                }
+                Ok(receipt)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if config.verification.enabled {
+            verify_receipts(row.number, record, &receipts, &config.verification)?;
+        }
+        for (spec, receipt) in (&record.receipts).into_iter().zip(receipts.iter()) {
+            let entry = history::Entry {
+                report: report_id.to_owned(),
+                created,
+                receipt: spec.to_string(),
+                fingerprint: receipt.fingerprint.clone(),
+            };
+            check_duplicate(row.number, &entry, &submitted, config.history.strict)?;
+            submitted.push((row.number, entry));
         }
-        for path in receipt_paths {
-            let document = pdf::load_receipt(path)?;
-            documents.push(document);
+        // the separation page is created once the number of receipt pages is known
+        let separation = if config.layout.separation_pages {
+            Some(pages.reserve(idx)?)
+        } else {
+            None
+        };
+        if receipts.is_empty() {
+            without_receipts.push(row.number);
+            if config.receipts.declaration_pages {
+                let statement = config
+                    .receipts
+                    .declaration
+                    .as_deref()
+                    .unwrap_or(pdf::DEFAULT_DECLARATION);
+                let document = pdf::declaration_page(
+                    row,
+                    &config.name,
+                    statement,
+                    config.layout.page_size.dimensions(),
+                )?;
+                pages.push(idx, document)?;
+            }
         }
+        let mut receipt_outline = Vec::new();
+        for receipt in receipts {
+            let label = format!("#{:02}", row.number);
+            if let Some(offset) = pages.push_receipt(idx, receipt, label)? {
+                let document = pages
+                    .documents
+                    .last()
+                    .expect("Receipt was just pushed. qed");
+                receipt_outline.extend(
+                    pdf::extract_outline(document)
+                        .into_iter()
+                        .map(|child| child.offset(offset)),
+                );
+            }
+        }
+        if let Some(separation) = separation {
+            pages.flush()?;
+            let receipt_pages = pages.count - pages.records[idx].start - 1;
+            let document =
+                pdf::separation_page(row, receipt_pages, config.layout.page_size.dimensions())?;
+            pages.documents[separation] = document;
+        }
+        receipt_outlines.push(receipt_outline);
+    }
+    pages.flush()?;
+
+    if !without_receipts.is_empty() {
+        log::warn!(
+            "Rows {} have no receipts{}",
+            without_receipts.iter().join(", "),
+            if config.receipts.declaration_pages {
+                ", adding declaration pages to be signed instead"
+            } else {
+                ""
+            }
+        );
     }
 
     log::info!("Receipt document loading complete");
 
-    let tabular = pdf::tabular(bankinfo, companyinfo, &rows, totals, learning_budget)?;
-
+    let summary = serde_json::json!({
+        "report": report_id,
+        "employee": config.name,
+        "rows": rows.iter().map(Row::to_json).collect::<Vec<_>>(),
+        "totals": totals.to_json(),
+    });
+
+    let total = totals.brutto;
+
+    let sign_off = config.sign_off.sign_off(&config.name);
+    let girocode = if config.girocode.enabled {
+        let reference = config.girocode.reference.as_deref().unwrap_or(report_id);
+        let girocode = pdf::GiroCode::new(&bankinfo, total, reference);
+        match girocode.payload() {
+            Ok(_) => Some(girocode),
+            Err(e) => {
+                log::warn!("Omitting the GiroCode: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let (tabular, row_areas) = pdf::tabular(
+        bankinfo,
+        companyinfo,
+        &rows,
+        totals,
+        learning_budget,
+        sign_off.as_ref(),
+        girocode.as_ref(),
+    )?;
+    let summary_pages = tabular.get_pages().len();
+
+    let mut documents = pages.documents;
     documents.insert(0, tabular);
 
-    let x = pdf::combine(&mut documents)?;
+    let mut x = pdf::combine(&mut documents)?;
+
+    // make the page indices absolute
+    let shared_pages = pages
+        .shared
+        .iter()
+        .map(|page| page + summary_pages)
+        .collect::<Vec<_>>();
+    let record_pages = pages
+        .records
+        .into_iter()
+        .map(|pages| (pages.start + summary_pages)..(pages.end + summary_pages))
+        .collect::<Vec<_>>();
+
+    let mut outline = Vec::with_capacity(records.len() + 1);
+    outline.push(pdf::OutlineEntry::new("Summary", 0));
+    for ((record, pages), receipt_outline) in records
+        .iter()
+        .zip(record_pages.iter())
+        .zip(receipt_outlines)
+    {
+        // records without receipts point to the summary
+        let page = if pages.is_empty() { 0 } else { pages.start };
+        let mut entry = pdf::OutlineEntry::new(
+            format!(
+                "{} – {} – {}",
+                record.date.format("%Y-%m-%d"),
+                record.company,
+                record.description
+            ),
+            page,
+        );
+        entry.children = receipt_outline
+            .into_iter()
+            .map(|child| child.offset(summary_pages))
+            .collect();
+        outline.push(entry);
+    }
+    pdf::add_outline(&mut x, &outline)?;
+
+    // link the table rows to the receipts of the record
+    let summary_page = *x
+        .get_pages()
+        .values()
+        .next()
+        .ok_or_else(|| eyre!("Combined document has no pages"))?;
+    let page_ids = x.get_pages().into_values().collect::<Vec<_>>();
+    for (area, pages) in row_areas.into_iter().zip(record_pages.iter()) {
+        if !pages.is_empty() {
+            pdf::add_link(&mut x, summary_page, area, page_ids[pages.start])?;
+        }
+    }
+
+    let layout = &config.layout;
+    let footer = &config.footer;
+    let page_total = page_ids.len();
+    let stamp_receipts = layout.back_links || layout.stamp_receipts;
+    let first_stamped = match (footer.enabled, stamp_receipts) {
+        (true, _) => 0,
+        (false, true) => summary_pages,
+        (false, false) => page_total,
+    };
+    let stamped_pages = (first_stamped..page_total).collect::<Vec<_>>();
+    let references = rows.iter().map(Row::reference).collect::<Vec<_>>();
+    pdf::overlay(&mut x, &stamped_pages, |canvas| {
+        if canvas.page >= summary_pages {
+            if layout.back_links {
+                pdf::back_link(canvas, 0)?;
+            }
+            let record = record_pages
+                .iter()
+                .position(|pages| pages.contains(&canvas.page));
+            // receipts on shared pages are labelled already
+            let shared_page = shared_pages.contains(&canvas.page);
+            if let (true, Some(record), false) = (layout.stamp_receipts, record, shared_page) {
+                // separation pages carry the reference already
+                let separation_page =
+                    layout.separation_pages && record_pages[record].start == canvas.page;
+                if !separation_page {
+                    pdf::receipt_stamp(canvas, &references[record])?;
+                }
+            }
+        }
+        if footer.enabled {
+            let label = format!(
+                "{} · {} · page {} of {}",
+                report_id,
+                config.name,
+                canvas.page + 1,
+                page_total
+            );
+            pdf::page_footer(canvas, &label, footer.position, footer.font_size)?;
+        }
+        Ok(())
+    })?;
+
+    let attachments = &config.attachments;
+    let mut files = Vec::new();
+    if let (true, Some(source)) = (attachments.source, source) {
+        let name = source
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        files.push(pdf::Attachment::from_path(
+            source,
+            name,
+            "Input data",
+            "Source",
+        )?);
+    }
+    if attachments.summary {
+        files.push(pdf::Attachment {
+            name: "summary.json".to_owned(),
+            description: "Summary of all rows and totals".to_owned(),
+            mime: "application/json".to_owned(),
+            relationship: "Data",
+            content: serde_json::to_vec_pretty(&summary)?,
+        });
+    }
+    if attachments.receipts {
+        for (record, row) in records.iter().zip(rows.iter()) {
+            // the same file might be given several times with different pages
+            for spec in record
+                .receipts
+                .into_iter()
+                .unique_by(|spec| (&spec.path, &spec.member))
+            {
+                let file = spec.member.as_ref().unwrap_or(&spec.path);
+                let name = format!(
+                    "receipt-{:02}-{}",
+                    row.number,
+                    file.file_name().unwrap_or_default().to_string_lossy()
+                );
+                let attachment = if let Some(ref member) = spec.member {
+                    let content = archive::read_member(&spec.path, member)?;
+                    pdf::Attachment::from_content(
+                        member,
+                        content,
+                        name,
+                        row.reference(),
+                        "Supplement",
+                    )
+                } else {
+                    pdf::Attachment::from_path(&spec.path, name, row.reference(), "Supplement")?
+                };
+                files.push(attachment);
+            }
+        }
+    }
+    pdf::embed_files(&mut x, &files)?;
+
+    let metadata = &config.metadata;
+    let period = match (
+        rows.iter().map(|row| row.date).min(),
+        rows.iter().map(|row| row.date).max(),
+    ) {
+        (Some(first), Some(last)) if first == last => first.format("%Y-%m-%d").to_string(),
+        (Some(first), Some(last)) => {
+            format!("{} – {}", first.format("%Y-%m-%d"), last.format("%Y-%m-%d"))
+        }
+        _ => "-".to_owned(),
+    };
+    let mut keywords = vec!["reimbursement".to_owned()];
+    if learning_budget {
+        keywords.push("learning budget".to_owned());
+    }
+    keywords.extend(rows.iter().map(|row| row.company.clone()).unique());
+    keywords.extend(metadata.keywords.iter().cloned());
+    let info = pdf::ReportInfo {
+        title: metadata
+            .title
+            .clone()
+            .unwrap_or_else(|| format!("Reimbursement request {}", report_id)),
+        author: config.name.clone(),
+        subject: format!("Expenses {}, total {} €", period, total),
+        keywords,
+        custom: metadata.custom.clone(),
+    };
+    pdf::set_document_info(&mut x, &info)?;
+
+    if config.pdfa {
+        pdf::convert_to_pdfa(&mut x)?;
+    }
 
     log::info!("Document creation complete");
 
-    Ok(x)
+    let submitted = submitted.into_iter().map(|(_, entry)| entry).collect();
+    Ok((x, total, submitted))
 }
 
 fn run() -> Result<()> {
@@ -224,7 +749,7 @@ fn run() -> Result<()> {
         return Ok(());
     }
 
-    let config = if let Some(config) = args.flag_config {
+    let mut config = if let Some(config) = args.flag_config {
         Config::from_file(&config)
     } else {
         log::info!(
@@ -234,11 +759,57 @@ fn run() -> Result<()> {
         Config::load_user_config()
     }?;
 
+    if args.flag_back_links {
+        config.layout.back_links = true;
+    }
+    if args.flag_separation_pages {
+        config.layout.separation_pages = true;
+    }
+    if args.flag_n_up {
+        config.n_up.enabled = true;
+    }
+    if args.flag_attach_receipts {
+        config.attachments.receipts = true;
+    }
+    if args.flag_pdfa {
+        config.pdfa = true;
+    }
+    if args.flag_sign {
+        config.signature.enabled = true;
+    }
+    if args.flag_verify {
+        config.verification.enabled = true;
+    }
+
     if args.cmd_config {
         println!("{:?}", config);
         return Ok(());
     }
 
+    let encryption = &config.encryption;
+    let user_password = password(
+        args.flag_user_password,
+        "SHINYPENNY_USER_PASSWORD",
+        encryption.user_password_command.as_deref(),
+    )?;
+    let owner_password = password(
+        args.flag_owner_password,
+        "SHINYPENNY_OWNER_PASSWORD",
+        encryption.owner_password_command.as_deref(),
+    )?;
+    let encrypt = user_password.is_some() || owner_password.is_some();
+    if encrypt && config.pdfa {
+        bail!("PDF/A documents must not be encrypted, drop either the passwords or `--pdfa`");
+    }
+
+    let signer = if config.signature.enabled {
+        Some(signer(&config.signature)?)
+    } else {
+        None
+    };
+
+    let max_size = args.flag_max_size.as_deref().map(parse_size).transpose()?;
+
     let bankinfo = BankInfo::new(&config.name, config.iban)?;
 
     let dest = if let Some(dest) = args.arg_dest {
@@ -309,7 +880,8 @@ fn run() -> Result<()> {
             rec.receipts = rec
                 .receipts
                 .into_iter()
-                .try_fold::<_, _, Result<Receipts>>(Receipts::default(), |mut acc, path| {
+                .try_fold::<_, _, Result<Receipts>>(Receipts::default(), |mut acc, spec| {
+                    let path = &spec.path;
                     let resolved = if path.is_absolute() {
                         path.to_owned()
                     } else {
@@ -324,11 +896,29 @@ fn run() -> Result<()> {
                         resolved.display(),
                         canon.display()
                     );
-                    acc.insert(canon);
+                    // directories and whole archives turn into all receipts within
+                    let spec = ReceiptSpec {
+                        path: canon,
+                        ..spec.clone()
+                    };
+                    for spec in spec.expand()? {
+                        acc.insert(spec);
+                    }
                     Ok(acc)
                 })?;
         }
 
+        if config.receipts.unreferenced {
+            let unreferenced = unreferenced_receipts(base, &data, &dest)?;
+            if !unreferenced.is_empty() {
+                log::warn!(
+                    "Receipts in {} which no record refers to: {}",
+                    base.display(),
+                    unreferenced.iter().map(|path| path.display()).join(", ")
+                );
+            }
+        }
+
         data
     } else {
         // create a single record from the provided commandline flags
@@ -352,7 +942,15 @@ fn run() -> Result<()> {
             brutto: args
                 .flag_brutto
                 .expect("docopt assured brutto has a value. qed"),
-            receipts: args.arg_receipts,
+            receipts: args
+                .arg_receipts
+                .into_iter()
+                .try_fold::<_, _, Result<Receipts>>(Receipts::default(), |mut acc, spec| {
+                    for spec in spec.clone().expand()? {
+                        acc.insert(spec);
+                    }
+                    Ok(acc)
+                })?,
         }]
     };
 
@@ -368,17 +966,255 @@ fn run() -> Result<()> {
     let company = &config.company;
     let companyinfo = CompanyInfo::new(&company.name, &company.address, company.image.clone())?;
 
-    let mut document = create_pdf(&data, bankinfo, companyinfo, args.flag_learning)?;
+    let report_id = config.footer.report_id.clone().unwrap_or_else(|| {
+        dest.file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let creditor = bankinfo.clone();
+    let (mut document, total, submitted) = create_pdf(
+        &data,
+        bankinfo,
+        companyinfo,
+        &config,
+        &report_id,
+        args.flag_csv.as_deref(),
+        args.flag_learning,
+    )?;
+
+    let mut history = if config.history.enabled {
+        let history = history::History::load(&config.history.path()?)?;
+        let run = history::run_key(&dest, &report_id)?;
+        check_history(&submitted, &history, &run, config.history.strict)?;
+        Some((history, run))
+    } else {
+        None
+    };
+
+    let serialize = |mut document: Document| -> Result<Vec<u8>> {
+        if let Some(ref signer) = signer {
+            let signature = &config.signature;
+            let info = pdf::SignatureInfo {
+                name: signer.name().unwrap_or_else(|| config.name.clone()),
+                reason: Some(
+                    signature
+                        .reason
+                        .clone()
+                        .unwrap_or_else(|| "Reimbursement request".to_owned()),
+                ),
+                location: signature.location.clone(),
+                contact: signature.contact.clone(),
+            };
+            let area = match config.sign_off.sign_off(&config.name) {
+                Some(sign_off) => sign_off.signature_area()?,
+                None => None,
+            }
+            .unwrap_or_else(pdf::signature_area);
+            pdf::prepare_signature(&mut document, 0, area, &info)?;
+        }
+
+        document.compress();
+        document.prune_objects();
+
+        if encrypt {
+            let permissions = pdf::Permissions {
+                print: encryption.allow_printing,
+                modify: encryption.allow_modification,
+                copy: encryption.allow_copying,
+                annotate: encryption.allow_annotations,
+            };
+            pdf::encrypt(
+                &mut document,
+                encryption.algorithm,
+                user_password.as_deref().unwrap_or_default(),
+                owner_password.as_deref(),
+                permissions,
+            )?;
+        }
+
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes)?;
+        if let Some(ref signer) = signer {
+            pdf::sign(&mut bytes, signer)?;
+        }
+        Ok(bytes)
+    };
+
+    // keep the original images around to compress them harder if needed
+    let pristine = max_size.map(|_| document.clone());
+    let options = config.images.options(config.layout.page_size);
+    pdf::optimize_images(&mut document, &options)?;
+    let mut bytes = serialize(document)?;
+    if let (Some(max_size), Some(pristine)) = (max_size, pristine) {
+        let mut step = 1;
+        while bytes.len() as u64 > max_size {
+            if step > MAX_SIZE_STEPS {
+                bail!(
+                    "Report of {} bytes still exceeds the maximum size of {} bytes, consider `bilevel` images or fewer attachments",
+                    bytes.len(),
+                    max_size
+                );
+            }
+            let options = options.tightened(step);
+            log::info!(
+                "Report of {} bytes exceeds {} bytes, retrying at {:.0} dpi with jpeg quality {}",
+                bytes.len(),
+                max_size,
+                options.max_dpi,
+                options.quality.unwrap_or(pdf::DEFAULT_JPEG_QUALITY)
+            );
+            let mut document = pristine.clone();
+            pdf::optimize_images(&mut document, &options)?;
+            bytes = serialize(document)?;
+            step += 1;
+        }
+    }
+    fs::write(dest, bytes)?;
+
+    if let Some((ref mut history, ref run)) = history {
+        history.record(run, submitted)?;
+    }
+
+    if let Some(ref path) = args.flag_sepa {
+        let reference = config.sepa.reference.as_deref().unwrap_or(&report_id);
+        let transfer = sepa::Transfer::new(&report_id, &creditor, total, reference)?;
+        write_credit_transfer(path, &config, transfer)?;
+    }
 
-    // size would be way too large, but this does not do too much
-    document.compress();
-    document.prune_objects();
+    Ok(())
+}
 
-    document.save(dest)?;
+/// Receipt files in the directory `base` which none of `records` refers
+/// to, the report written to `dest` aside.
+fn unreferenced_receipts(base: &Path, records: &[Record], dest: &Path) -> Result<Vec<PathBuf>> {
+    let referenced = records
+        .iter()
+        .flat_map(|record| &record.receipts)
+        .map(|spec| spec.path.as_path())
+        .collect::<std::collections::HashSet<_>>();
+    let dest = dest.canonicalize().unwrap_or_else(|_| dest.to_owned());
+    let mut unreferenced = Vec::new();
+    for path in archive::directory_receipts(base)? {
+        let canon = path.canonicalize()?;
+        if !referenced.contains(canon.as_path()) && canon != dest {
+            unreferenced.push(path.strip_prefix(base).unwrap_or(&path).to_owned());
+        }
+    }
+    Ok(unreferenced)
+}
 
+/// Attempts to tighten the image compression to meet `--max-size`.
+const MAX_SIZE_STEPS: u32 = 6;
+
+/// Parse a size in bytes with an optional decimal unit, i.e. `10M` or `500kB`.
+fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (value, unit) = size.split_at(split);
+    let value = value
+        .parse::<f64>()
+        .wrap_err_with(|| eyre!("Invalid size `{}`", size))?;
+    let factor = match unit.trim().to_lowercase().trim_end_matches('b') {
+        "" => 1.,
+        "k" => 1e3,
+        "m" => 1e6,
+        "g" => 1e9,
+        _ => bail!("Unknown unit of size `{}`, expected one of k, M or G", size),
+    };
+    Ok((value * factor) as u64)
+}
+
+/// Write the SEPA credit transfer file at `path`, keeping the previous
+/// transfers of the file when batching.
+fn write_credit_transfer(path: &Path, config: &Config, transfer: sepa::Transfer) -> Result<()> {
+    let sepa = &config.sepa;
+    let debtor = sepa.debtor.as_ref().ok_or_else(|| {
+        eyre!("A SEPA credit transfer requires the `[sepa.debtor]` account in the config")
+    })?;
+    let mut credit_transfer = sepa::CreditTransfer {
+        version: sepa.version,
+        debtor: sepa::Debtor {
+            name: debtor
+                .name
+                .clone()
+                .unwrap_or_else(|| config.company.name.clone()),
+            iban: debtor.iban,
+            bic: debtor.bic.clone(),
+        },
+        execution_date: chrono::Local::today().naive_local()
+            + chrono::Duration::days(sepa.execution_delay.into()),
+        transfers: Vec::new(),
+    };
+    if sepa.batch && path.exists() {
+        credit_transfer
+            .load_transfers(&fs::read_to_string(path)?)
+            .wrap_err_with(|| eyre!("Failed to batch with {}", path.display()))?;
+    }
+    credit_transfer.add(transfer);
+    fs::write(path, credit_transfer.to_xml())?;
     Ok(())
 }
 
+/// Obtain a password from the commandline flag, the environment
+/// variable `env` or the output of `command`, in that order.
+fn password(flag: Option<String>, env: &str, command: Option<&str>) -> Result<Option<String>> {
+    if flag.is_some() {
+        return Ok(flag);
+    }
+    if let Ok(password) = std::env::var(env) {
+        return Ok(Some(password));
+    }
+    let command = if let Some(command) = command {
+        command
+    } else {
+        return Ok(None);
+    };
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .wrap_err_with(|| eyre!("Failed to run password command `{}`", command))?;
+    if !output.status.success() {
+        bail!(
+            "Password command `{}` failed with {}",
+            command,
+            output.status
+        );
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .wrap_err_with(|| eyre!("Password command `{}` printed invalid utf8", command))?;
+    let password = stdout.lines().next().unwrap_or_default().to_owned();
+    if password.is_empty() {
+        bail!("Password command `{}` printed no password", command);
+    }
+    Ok(Some(password))
+}
+
+/// Load the key and certificates to sign with.
+fn signer(config: &config::SignatureConfig) -> Result<pdf::Signer> {
+    let password = password(
+        None,
+        "SHINYPENNY_SIGNATURE_PASSWORD",
+        config.password_command.as_deref(),
+    )?;
+    match (&config.pkcs12, &config.certificate, &config.key) {
+        (Some(pkcs12), None, None) => {
+            pdf::Signer::from_pkcs12(pkcs12, password.as_deref().unwrap_or_default())
+        }
+        (None, Some(certificate), Some(key)) => {
+            pdf::Signer::from_pem(certificate, key, password.as_deref())
+        }
+        _ => bail!(
+            "Signing requires either `pkcs12` or both `certificate` and `key` to be configured"
+        ),
+    }
+}
+
 fn data_plumbing(mut buffered: impl BufRead, separator: u8) -> Result<Vec<Record>> {
     let mut data = Vec::<Record>::with_capacity(256);
 
@@ -455,6 +1291,7 @@ fn main() -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
 
     static DATA: &[(&'static str, usize /*, &[Record]*/)] = &[
         (
@@ -504,4 +1341,35 @@ Device: Superblaster 2k21|abc| 2020-09-20   |assets/spensiv.pdf |95.00 ¥ @ 1.20
             assert_eq!(data.1, rows.len());
         }
     }
+
+    #[test]
+    fn unreferenced() {
+        let base =
+            std::env::temp_dir().join(format!("shinypenny-unreferenced-{}", std::process::id()));
+        fs::create_dir_all(base.join("march")).unwrap();
+        for file in [
+            "taxi.pdf",
+            "hotel.jpg",
+            "notes.md",
+            "report.pdf",
+            "march/flight.pdf",
+        ] {
+            fs::write(base.join(file), b"").unwrap();
+        }
+        let base = base.canonicalize().unwrap();
+        let record = Record {
+            date: chrono::NaiveDate::from_ymd(2021, 3, 4),
+            description: "Taxi".to_owned(),
+            company: "Cab Corp".to_owned(),
+            netto: Expense::from_str("4 €").unwrap(),
+            tax: Percentage::from_str("19%").unwrap(),
+            brutto: Expense::from_str("4.76 €").unwrap(),
+            receipts: Receipts::from(vec![base.join("taxi.pdf")]),
+        };
+
+        let unreferenced =
+            unreferenced_receipts(&base, &[record], &base.join("report.pdf")).unwrap();
+        assert_eq!(unreferenced, vec![PathBuf::from("hotel.jpg")]);
+        fs::remove_dir_all(base).unwrap();
+    }
 }
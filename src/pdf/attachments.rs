@@ -0,0 +1,151 @@
+//! Embedded files, listed in the `EmbeddedFiles` name tree of the document.
+
+use super::strings::encode_text_string;
+use crate::errors::*;
+
+use lopdf::{dictionary, Dictionary, Document, Object, Stream};
+
+use std::path::Path;
+
+use fs_err as fs;
+
+/// A file to be embedded into the document.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    /// File name as presented by the viewer, must be unique.
+    pub name: String,
+    pub description: String,
+    pub mime: String,
+    /// Relation of the file to the document, i.e. `Source`, `Data` or `Supplement`.
+    pub relationship: &'static str,
+    pub content: Vec<u8>,
+}
+
+impl Attachment {
+    /// Read the attachment content from `path`, the mime type is derived
+    /// from the content or the file extension.
+    pub fn from_path(
+        path: impl AsRef<Path>,
+        name: impl Into<String>,
+        description: impl Into<String>,
+        relationship: &'static str,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read(path)
+            .wrap_err_with(|| eyre!("Failed to read attachment {}", path.display()))?;
+        Ok(Self::from_content(
+            path,
+            content,
+            name,
+            description,
+            relationship,
+        ))
+    }
+
+    /// Attach `content` read from `path` elsewhere, i.e. an archive member,
+    /// the mime type is derived from the content or the file extension.
+    pub fn from_content(
+        path: impl AsRef<Path>,
+        content: Vec<u8>,
+        name: impl Into<String>,
+        description: impl Into<String>,
+        relationship: &'static str,
+    ) -> Self {
+        let path = path.as_ref();
+        let mime = infer::get(&content)
+            .map(|kind| kind.mime_type())
+            .unwrap_or_else(|| {
+                match path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| ext.to_lowercase())
+                    .as_deref()
+                {
+                    Some("csv") => "text/csv",
+                    Some("json") => "application/json",
+                    Some("xml") => "application/xml",
+                    Some("txt") => "text/plain",
+                    _ => "application/octet-stream",
+                }
+            });
+        Self {
+            name: name.into(),
+            description: description.into(),
+            mime: mime.to_owned(),
+            relationship,
+            content,
+        }
+    }
+}
+
+/// Embed all `attachments` into `document`.
+///
+/// Replaces an existing `EmbeddedFiles` name tree and references the file
+/// specifications from the catalog's `AF` array as well.
+pub fn embed_files(document: &mut Document, attachments: &[Attachment]) -> Result<()> {
+    if attachments.is_empty() {
+        return Ok(());
+    }
+    let now = chrono::Local::now().format("D:%Y%m%d%H%M%S").to_string();
+
+    let mut sorted = attachments.iter().collect::<Vec<_>>();
+    // the name tree must be sorted by key
+    sorted.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+
+    let mut names = Vec::with_capacity(attachments.len() * 2);
+    let mut filespecs = Vec::with_capacity(attachments.len());
+    for attachment in sorted {
+        let stream = Stream::new(
+            dictionary! {
+                "Type" => "EmbeddedFile",
+                "Subtype" => Object::Name(attachment.mime.as_bytes().to_vec()),
+                "Params" => dictionary! {
+                    "Size" => attachment.content.len() as i64,
+                    "ModDate" => Object::string_literal(now.as_str()),
+                },
+            },
+            attachment.content.clone(),
+        );
+        let stream = document.add_object(stream);
+
+        let filespec = document.add_object(dictionary! {
+            "Type" => "Filespec",
+            "F" => encode_text_string(&attachment.name),
+            "UF" => encode_text_string(&attachment.name),
+            "Desc" => encode_text_string(&attachment.description),
+            "AFRelationship" => attachment.relationship,
+            "EF" => dictionary! {
+                "F" => stream,
+                "UF" => stream,
+            },
+        });
+        names.push(encode_text_string(&attachment.name));
+        names.push(Object::Reference(filespec));
+        filespecs.push(Object::Reference(filespec));
+    }
+    let tree = document.add_object(dictionary! {
+        "Names" => names,
+    });
+
+    let catalog_id = document
+        .trailer
+        .get(b"Root")
+        .and_then(Object::as_reference)
+        .map_err(|e| eyre!("Document has no catalog: {:?}", e))?;
+    let mut name_dictionary = document
+        .get_dictionary(catalog_id)
+        .and_then(|catalog| catalog.get(b"Names"))
+        .and_then(|names| document.dereference(names))
+        .and_then(|(_, names)| names.as_dict())
+        .cloned()
+        .unwrap_or_else(|_| Dictionary::new());
+    name_dictionary.set("EmbeddedFiles", tree);
+
+    let catalog = document
+        .get_object_mut(catalog_id)
+        .and_then(Object::as_dict_mut)
+        .map_err(|e| eyre!("Catalog is not a dictionary: {:?}", e))?;
+    catalog.set("Names", name_dictionary);
+    catalog.set("AF", filespecs);
+    Ok(())
+}
@@ -1,5 +1,8 @@
 use super::types::*;
 
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy)]
 pub struct Dimensions {
     pub height: Mm,
     pub width: Mm,
@@ -11,5 +14,49 @@ pub const DIN_A4: Dimensions = Dimensions {
     width: Mm(210.),
 };
 
+/// DIN A5 in mm dimensions.
+pub const DIN_A5: Dimensions = Dimensions {
+    height: Mm(210.),
+    width: Mm(148.),
+};
+
+/// US Letter in mm dimensions.
+pub const US_LETTER: Dimensions = Dimensions {
+    height: Mm(279.4),
+    width: Mm(215.9),
+};
+
+/// US Legal in mm dimensions.
+pub const US_LEGAL: Dimensions = Dimensions {
+    height: Mm(355.6),
+    width: Mm(215.9),
+};
+
+/// Page sizes which can be selected in the configuration.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PageSize {
+    #[default]
+    A4,
+    A5,
+    Letter,
+    Legal,
+}
+
+impl PageSize {
+    pub fn dimensions(self) -> Dimensions {
+        match self {
+            Self::A4 => DIN_A4,
+            Self::A5 => DIN_A5,
+            Self::Letter => US_LETTER,
+            Self::Legal => US_LEGAL,
+        }
+    }
+}
+
 pub static TTF_REGULAR: &'static [u8] = include_bytes!("../../assets/Roboto-Regular.ttf");
 pub static TTF_BOLD: &'static [u8] = include_bytes!("../../assets/Roboto-Bold.ttf");
+pub static TTF_MONO: &[u8] = include_bytes!("../../assets/DejaVuSansMono.ttf");
+
+/// sRGB IEC61966-2.1 color profile, used as output intent for PDF/A.
+pub static ICC_SRGB: &[u8] = include_bytes!("../../assets/sRGB.icc");
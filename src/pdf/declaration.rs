@@ -0,0 +1,109 @@
+//! Declaration page standing in for the receipt of a record which has none,
+//! i.e. for a lost receipt or a tip, to be signed by the employee.
+
+use super::super::types::*;
+use super::constants::{Dimensions, TTF_BOLD, TTF_REGULAR};
+use super::helper::flush_pdf_ops;
+use super::tabular::{text, Alignment};
+use super::textual::wrap;
+use super::types::*;
+use crate::errors::*;
+
+use printpdf::{Line, PdfDocument};
+
+/// Statement of the declaration, unless configured otherwise.
+pub const DEFAULT_DECLARATION: &str = "I hereby declare that the expense listed above was incurred for business purposes and that no receipt was issued for it or the receipt was lost.";
+
+/// Create a declaration page for `row` by the employee `name`.
+pub fn declaration_page(
+    row: &Row,
+    name: &str,
+    statement: &str,
+    dimensions: Dimensions,
+) -> Result<lopdf::Document> {
+    let (doc, page1, layer1) = PdfDocument::new(
+        "Declaration",
+        dimensions.width,
+        dimensions.height,
+        "Layer 1",
+    );
+    let active_layer = doc.get_page(page1).get_layer(layer1);
+
+    let font = doc.add_external_font(TTF_REGULAR)?;
+    let bold = doc.add_external_font(TTF_BOLD)?;
+
+    // font size
+    let size = 11;
+
+    let width = Pt::from(dimensions.width);
+    let height = Pt::from(dimensions.height);
+    let left = width * 0.10;
+    let right = width * 0.90;
+
+    let mut anchor = Point {
+        x: left,
+        y: height - Pt::from(Mm(35.)),
+    };
+    text(
+        &active_layer,
+        anchor,
+        &format!("#{:02} Declaration in lieu of a receipt", row.number),
+        &bold,
+        18,
+        Alignment::Left,
+    )?;
+
+    let entries = [
+        ("Description:", row.description.clone()),
+        ("Date:", row.date.format("%Y-%m-%d").to_string()),
+        ("Company:", row.company.clone()),
+        ("Brutto:", row.brutto.to_string()),
+    ];
+    anchor.y -= Pt::from(Mm(20.));
+    for (label, value) in entries {
+        anchor.x = width * 0.35;
+        text(&active_layer, anchor, label, &font, size, Alignment::Right)?;
+        anchor.x += Pt(10.0);
+        text(&active_layer, anchor, &value, &bold, size, Alignment::Left)?;
+        anchor.y -= Pt(22.0);
+    }
+
+    anchor.x = left;
+    anchor.y -= Pt::from(Mm(10.));
+    for line in wrap(statement, TTF_REGULAR, size, right - left)? {
+        text(&active_layer, anchor, &line, &font, size, Alignment::Left)?;
+        anchor.y -= Pt(size as f64 * 1.4);
+    }
+
+    // room for the signature above the line
+    anchor.y -= Pt::from(Mm(30.));
+    let line = Line {
+        points: vec![
+            (anchor, false),
+            (
+                Point {
+                    x: right,
+                    y: anchor.y,
+                },
+                false,
+            ),
+        ],
+        is_closed: false,
+        has_fill: false,
+        has_stroke: true,
+        is_clipping_path: false,
+    };
+    active_layer.set_outline_thickness(0.5);
+    active_layer.add_shape(line);
+    anchor.y -= Pt(14.0);
+    text(
+        &active_layer,
+        anchor,
+        &format!("Date, signature of {}", name),
+        &font,
+        9,
+        Alignment::Left,
+    )?;
+
+    flush_pdf_ops(doc)
+}
@@ -0,0 +1,1161 @@
+//! Encryption with the standard security handler.
+//!
+//! Supports AES-128 (`V 4`, `R 4`) and AES-256 (`V 5`, `R 6`), strings
+//! and streams of all objects are encrypted, including the metadata.
+//!
+//! Decryption of receipts additionally covers the older RC4 based
+//! revisions `R 2` and `R 3` as well as `R 5`.
+
+use crate::errors::*;
+
+use lopdf::content::Content;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use openssl::hash::{hash, MessageDigest};
+use openssl::rand::rand_bytes;
+use openssl::symm::{Cipher, Crypter, Mode};
+use serde::Deserialize;
+
+/// Padding of passwords for the `R 4` key derivation.
+const PADDING: [u8; 32] = [
+    0x28, 0xBF, 0x4E, 0x5E, 0x4E, 0x75, 0x8A, 0x41, 0x64, 0x00, 0x4E, 0x56, 0xFF, 0xFA, 0x01, 0x08,
+    0x2E, 0x2E, 0x00, 0xB6, 0xD0, 0x68, 0x3E, 0x80, 0x2F, 0x0C, 0xA9, 0xFE, 0x64, 0x53, 0x69, 0x7A,
+];
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncryptionAlgorithm {
+    #[serde(rename = "aes-128")]
+    Aes128,
+    #[default]
+    #[serde(rename = "aes-256")]
+    Aes256,
+}
+
+/// What a user who only knows the user password may do.
+#[derive(Debug, Clone, Copy)]
+pub struct Permissions {
+    pub print: bool,
+    pub modify: bool,
+    pub copy: bool,
+    pub annotate: bool,
+}
+
+impl Permissions {
+    /// The `P` entry of the encryption dictionary.
+    fn bits(&self) -> i32 {
+        // reserved bits 7, 8 and 13 to 32 must be set, so must
+        // the one for extraction for accessibility purposes
+        let mut p = 0xFFFF_F0C0u32 | 1 << 9;
+        if self.print {
+            p |= 1 << 2 | 1 << 11;
+        }
+        if self.modify {
+            p |= 1 << 3 | 1 << 10;
+        }
+        if self.copy {
+            p |= 1 << 4;
+        }
+        if self.annotate {
+            p |= 1 << 5 | 1 << 8;
+        }
+        p as i32
+    }
+}
+
+/// Encrypt `document` in place, to be called right before saving it.
+///
+/// Without an owner password, a random one is used, so the permissions
+/// can not be lifted.
+pub fn encrypt(
+    document: &mut Document,
+    algorithm: EncryptionAlgorithm,
+    user_password: &str,
+    owner_password: Option<&str>,
+    permissions: Permissions,
+) -> Result<()> {
+    let owner_password = match owner_password {
+        Some(owner_password) => owner_password.to_owned(),
+        None => random_bytes(16)?
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    };
+    let p = permissions.bits();
+    let id = file_identifier(document)?;
+
+    let (file_key, mut encrypt) = match algorithm {
+        EncryptionAlgorithm::Aes128 => {
+            let user = pdf_doc_encoded(user_password)?;
+            let owner = pdf_doc_encoded(&owner_password)?;
+            let o = owner_entry_legacy(&owner, &user, 4, 16)?;
+            let file_key = file_key_legacy(&user, &o, p, &id, 4, true, 16)?;
+            let u = user_entry_legacy(&file_key, &id, 4)?;
+            let encrypt = dictionary! {
+                "V" => 4,
+                "R" => 4,
+                "Length" => 128,
+                "CF" => dictionary! {
+                    "StdCF" => dictionary! {
+                        "AuthEvent" => "DocOpen",
+                        "CFM" => "AESV2",
+                        "Length" => 16,
+                    },
+                },
+                "O" => Object::String(o, StringFormat::Hexadecimal),
+                "U" => Object::String(u, StringFormat::Hexadecimal),
+            };
+            (file_key, encrypt)
+        }
+        EncryptionAlgorithm::Aes256 => {
+            let user = utf8_password(user_password);
+            let owner = utf8_password(&owner_password);
+            let file_key = random_bytes(32)?;
+
+            let salts = random_bytes(16)?;
+            let (validation_salt, key_salt) = salts.split_at(8);
+            let mut u = hash_r6(user, validation_salt, &[])?.to_vec();
+            u.extend_from_slice(&salts);
+            let ue = aes_no_padding(
+                Mode::Encrypt,
+                Cipher::aes_256_cbc(),
+                &hash_r6(user, key_salt, &[])?,
+                Some(&[0u8; 16]),
+                &file_key,
+            )?;
+
+            let salts = random_bytes(16)?;
+            let (validation_salt, key_salt) = salts.split_at(8);
+            let mut o = hash_r6(owner, validation_salt, &u)?.to_vec();
+            o.extend_from_slice(&salts);
+            let oe = aes_no_padding(
+                Mode::Encrypt,
+                Cipher::aes_256_cbc(),
+                &hash_r6(owner, key_salt, &u)?,
+                Some(&[0u8; 16]),
+                &file_key,
+            )?;
+
+            let mut perms = (p as u32).to_le_bytes().to_vec();
+            perms.extend_from_slice(&[0xFF; 4]);
+            perms.extend_from_slice(b"Tadb");
+            perms.extend_from_slice(&random_bytes(4)?);
+            let perms = aes_no_padding(
+                Mode::Encrypt,
+                Cipher::aes_256_ecb(),
+                &file_key,
+                None,
+                &perms,
+            )?;
+
+            let encrypt = dictionary! {
+                "V" => 5,
+                "R" => 6,
+                "Length" => 256,
+                "CF" => dictionary! {
+                    "StdCF" => dictionary! {
+                        "AuthEvent" => "DocOpen",
+                        "CFM" => "AESV3",
+                        "Length" => 32,
+                    },
+                },
+                "O" => Object::String(o, StringFormat::Hexadecimal),
+                "U" => Object::String(u, StringFormat::Hexadecimal),
+                "OE" => Object::String(oe, StringFormat::Hexadecimal),
+                "UE" => Object::String(ue, StringFormat::Hexadecimal),
+                "Perms" => Object::String(perms, StringFormat::Hexadecimal),
+            };
+            (file_key, encrypt)
+        }
+    };
+    encrypt.set("Filter", "Standard");
+    encrypt.set("StmF", "StdCF");
+    encrypt.set("StrF", "StdCF");
+    encrypt.set("P", p);
+    encrypt.set("EncryptMetadata", true);
+
+    for (id, object) in document.objects.iter_mut() {
+        let key = object_key(algorithm, &file_key, *id)?;
+        encrypt_object(object, &key)?;
+    }
+
+    if algorithm == EncryptionAlgorithm::Aes256 {
+        // AES-256 is an extension to PDF 1.7
+        document.version = "1.7".to_owned();
+        let catalog_id = document
+            .trailer
+            .get(b"Root")
+            .and_then(Object::as_reference)
+            .map_err(|e| eyre!("Document has no catalog: {:?}", e))?;
+        let catalog = document
+            .get_object_mut(catalog_id)
+            .and_then(Object::as_dict_mut)
+            .map_err(|e| eyre!("Catalog is not a dictionary: {:?}", e))?;
+        catalog.set(
+            "Extensions",
+            dictionary! {
+                "ADBE" => dictionary! {
+                    "BaseVersion" => "1.7",
+                    "ExtensionLevel" => 8,
+                },
+            },
+        );
+    }
+
+    let encrypt = document.add_object(encrypt);
+    document.trailer.set("Encrypt", encrypt);
+    Ok(())
+}
+
+/// Whether `document` references an encryption dictionary.
+pub fn is_encrypted(document: &Document) -> bool {
+    document.trailer.has(b"Encrypt")
+}
+
+/// How strings or streams of an encrypted document are encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CryptMethod {
+    Identity,
+    Rc4,
+    Aes,
+}
+
+/// Parameters of the standard security handler of an encrypted document.
+#[derive(Debug)]
+struct SecurityHandler {
+    revision: i64,
+    /// Length of the file encryption key in bytes.
+    length: usize,
+    o: Vec<u8>,
+    u: Vec<u8>,
+    oe: Vec<u8>,
+    ue: Vec<u8>,
+    p: i32,
+    id: Vec<u8>,
+    encrypt_metadata: bool,
+    streams: CryptMethod,
+    strings: CryptMethod,
+}
+
+impl SecurityHandler {
+    fn from_document(document: &Document) -> Result<Self> {
+        let encrypt = match document.trailer.get(b"Encrypt") {
+            Ok(Object::Reference(id)) => document.get_dictionary(*id),
+            Ok(object) => object.as_dict(),
+            Err(e) => Err(e),
+        }
+        .map_err(|e| eyre!("Missing encryption dictionary: {:?}", e))?;
+
+        match encrypt.get(b"Filter").and_then(Object::as_name_str) {
+            Ok("Standard") => {}
+            Ok(filter) => bail!("Unsupported security handler {}", filter),
+            Err(e) => bail!("Missing security handler: {:?}", e),
+        }
+        let integer = |key: &[u8]| encrypt.get(key).and_then(Object::as_i64).ok();
+        let bytes = |key: &[u8]| {
+            encrypt
+                .get(key)
+                .and_then(Object::as_str)
+                .map(|s| s.to_vec())
+                .unwrap_or_default()
+        };
+        let version = integer(b"V").unwrap_or(0);
+        let revision = integer(b"R").unwrap_or(2);
+
+        let method = |key: &[u8]| -> Result<CryptMethod> {
+            if version < 4 {
+                return Ok(CryptMethod::Rc4);
+            }
+            let name = encrypt
+                .get(key)
+                .and_then(Object::as_name)
+                .unwrap_or(b"Identity");
+            if name == b"Identity" {
+                return Ok(CryptMethod::Identity);
+            }
+            let filter = encrypt
+                .get(b"CF")
+                .and_then(Object::as_dict)
+                .and_then(|cf| cf.get(name))
+                .and_then(Object::as_dict)
+                .map_err(|e| eyre!("Missing crypt filter: {:?}", e))?;
+            match filter.get(b"CFM").and_then(Object::as_name_str) {
+                Ok("V2") => Ok(CryptMethod::Rc4),
+                Ok("AESV2") | Ok("AESV3") => Ok(CryptMethod::Aes),
+                Ok("None") | Err(_) => Ok(CryptMethod::Identity),
+                Ok(cfm) => bail!("Unsupported crypt filter method {}", cfm),
+            }
+        };
+
+        let length = match (version, revision) {
+            (_, 5..=6) => 32,
+            (1, _) | (_, 2) => 5,
+            _ => (integer(b"Length").unwrap_or(40) as usize / 8).clamp(5, 16),
+        };
+        let id = document
+            .trailer
+            .get(b"ID")
+            .and_then(Object::as_array)
+            .ok()
+            .and_then(|id| id.first())
+            .and_then(|id| id.as_str().ok())
+            .map(|id| id.to_vec())
+            .unwrap_or_default();
+
+        Ok(Self {
+            revision,
+            length,
+            o: bytes(b"O"),
+            u: bytes(b"U"),
+            oe: bytes(b"OE"),
+            ue: bytes(b"UE"),
+            // large values of `P` are sometimes written unsigned
+            p: integer(b"P").unwrap_or(0) as i32,
+            id,
+            encrypt_metadata: encrypt
+                .get(b"EncryptMetadata")
+                .and_then(Object::as_bool)
+                .unwrap_or(true),
+            streams: method(b"StmF")?,
+            strings: method(b"StrF")?,
+        })
+    }
+
+    /// The file encryption key, if `password` is either the user or the owner password.
+    fn file_key(&self, password: &str) -> Result<Option<Vec<u8>>> {
+        match self.revision {
+            2..=4 => {
+                // such a password can not open documents of these revisions
+                let password = match pdf_doc_encoded(password) {
+                    Ok(password) => password,
+                    Err(_) => return Ok(None),
+                };
+                if let Some(key) = self.file_key_legacy(&password)? {
+                    return Ok(Some(key));
+                }
+                // algorithm 7, recover the user password from the owner password
+                let key = owner_key_legacy(&password, self.revision, self.length)?;
+                let user = match self.revision {
+                    2 => rc4(&key, &self.o),
+                    _ => rc4_rounds_reversed(&key, &self.o),
+                };
+                self.file_key_legacy(&user)
+            }
+            5..=6 => {
+                let password = utf8_password(password);
+                if self.u.len() < 48 || self.o.len() < 48 {
+                    bail!("Malformed encryption dictionary");
+                }
+                let digest = |salt: &[u8], user_entry: &[u8]| -> Result<Vec<u8>> {
+                    match self.revision {
+                        5 => Ok(hash(
+                            MessageDigest::sha256(),
+                            &[password, salt, user_entry].concat(),
+                        )?
+                        .to_vec()),
+                        _ => hash_r6(password, salt, user_entry),
+                    }
+                };
+                let (intermediate, encrypted_key) = if digest(&self.u[32..40], &[])? == self.u[..32]
+                {
+                    (digest(&self.u[40..48], &[])?, &self.ue)
+                } else if digest(&self.o[32..40], &self.u[..48])? == self.o[..32] {
+                    (digest(&self.o[40..48], &self.u[..48])?, &self.oe)
+                } else {
+                    return Ok(None);
+                };
+                let key = aes_no_padding(
+                    Mode::Decrypt,
+                    Cipher::aes_256_cbc(),
+                    &intermediate,
+                    Some(&[0u8; 16]),
+                    encrypted_key,
+                )?;
+                Ok(Some(key))
+            }
+            revision => bail!("Unsupported security handler revision {}", revision),
+        }
+    }
+
+    /// Algorithm 6, validate the user password of `R 2` to `R 4`.
+    fn file_key_legacy(&self, user: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = file_key_legacy(
+            user,
+            &self.o,
+            self.p,
+            &self.id,
+            self.revision,
+            self.encrypt_metadata,
+            self.length,
+        )?;
+        let u = user_entry_legacy(&key, &self.id, self.revision)?;
+        // only the first 16 bytes are significant beyond `R 2`
+        let significant = if self.revision == 2 { 32 } else { 16 };
+        let matches = self.u.len() >= significant && u[..significant] == self.u[..significant];
+        Ok(if matches { Some(key) } else { None })
+    }
+
+    fn object_key(&self, file_key: &[u8], id: ObjectId, method: CryptMethod) -> Result<Vec<u8>> {
+        if self.revision >= 5 {
+            return Ok(file_key.to_vec());
+        }
+        let mut input = file_key.to_vec();
+        input.extend_from_slice(&id.0.to_le_bytes()[..3]);
+        input.extend_from_slice(&id.1.to_le_bytes()[..2]);
+        if method == CryptMethod::Aes {
+            input.extend_from_slice(b"sAlT");
+        }
+        let mut key = md5(&input)?;
+        key.truncate((self.length + 5).min(16));
+        Ok(key)
+    }
+
+    fn decrypt_bytes(&self, key: &[u8], method: CryptMethod, data: &[u8]) -> Result<Vec<u8>> {
+        match method {
+            CryptMethod::Identity => Ok(data.to_vec()),
+            CryptMethod::Rc4 => Ok(rc4(key, data)),
+            // the initialization vector is prepended
+            CryptMethod::Aes if data.len() < 16 => Ok(Vec::new()),
+            CryptMethod::Aes => {
+                let cipher = match key.len() {
+                    16 => Cipher::aes_128_cbc(),
+                    _ => Cipher::aes_256_cbc(),
+                };
+                let (iv, data) = data.split_at(16);
+                Ok(openssl::symm::decrypt(cipher, key, Some(iv), data)?)
+            }
+        }
+    }
+
+    fn decrypt_object(&self, object: &mut Object, file_key: &[u8], id: ObjectId) -> Result<()> {
+        match object {
+            Object::String(content, _) => {
+                let key = self.object_key(file_key, id, self.strings)?;
+                *content = self.decrypt_bytes(&key, self.strings, content)?;
+            }
+            Object::Array(array) => {
+                for item in array.iter_mut() {
+                    self.decrypt_object(item, file_key, id)?;
+                }
+            }
+            Object::Dictionary(dict) => {
+                let signature = dict.type_is(b"Sig");
+                for (name, value) in dict.iter_mut() {
+                    if signature && name == b"Contents" {
+                        continue;
+                    }
+                    self.decrypt_object(value, file_key, id)?;
+                }
+            }
+            Object::Stream(stream) => {
+                for (_, value) in stream.dict.iter_mut() {
+                    self.decrypt_object(value, file_key, id)?;
+                }
+                let key = self.object_key(file_key, id, self.streams)?;
+                let content = self.decrypt_bytes(&key, self.streams, &stream.content)?;
+                stream.set_content(content);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Decrypt `document` in place, trying the empty password first and
+/// then each of `passwords`.
+///
+/// `buffer` are the raw bytes the document was loaded from, encrypted
+/// object streams can not be parsed on load and are recovered from there.
+pub fn decrypt(document: &mut Document, buffer: &[u8], passwords: &[String]) -> Result<()> {
+    let handler = SecurityHandler::from_document(document)?;
+
+    let mut file_key = None;
+    for password in std::iter::once("").chain(passwords.iter().map(String::as_str)) {
+        if let Some(key) = handler.file_key(password)? {
+            file_key = Some(key);
+            break;
+        }
+    }
+    let file_key = match file_key {
+        Some(file_key) => file_key,
+        None if passwords.is_empty() => {
+            bail!("Document is password protected, but no password is configured")
+        }
+        None => bail!(
+            "None of the {} configured passwords opens the document",
+            passwords.len()
+        ),
+    };
+
+    let encrypt_id = document
+        .trailer
+        .get(b"Encrypt")
+        .and_then(Object::as_reference)
+        .ok();
+    let mut object_streams = Vec::new();
+    for (id, object) in document.objects.iter_mut() {
+        if Some(*id) == encrypt_id {
+            continue;
+        }
+        if let Object::Stream(stream) = object {
+            if stream.dict.type_is(b"ObjStm") {
+                object_streams.push(*id);
+                continue;
+            }
+            // cross reference streams are never encrypted
+            if stream.dict.type_is(b"XRef")
+                || (!handler.encrypt_metadata && stream.dict.type_is(b"Metadata"))
+            {
+                continue;
+            }
+        }
+        handler.decrypt_object(object, &file_key, *id)?;
+    }
+
+    for id in object_streams {
+        document.objects.remove(&id);
+        let (dict, content) = raw_stream(document, buffer, id)
+            .ok_or_else(|| eyre!("Object stream {} {} R is missing", id.0, id.1))?;
+        let key = handler.object_key(&file_key, id, handler.streams)?;
+        let content = handler.decrypt_bytes(&key, handler.streams, &content)?;
+        let mut stream = Stream::new(dict, content);
+        stream.decompress();
+        for (member, object) in object_stream_members(&stream)
+            .ok_or_else(|| eyre!("Object stream {} {} R is malformed", id.0, id.1))?
+        {
+            document.objects.insert(member, object);
+        }
+    }
+
+    if let Some(id) = encrypt_id {
+        document.objects.remove(&id);
+    }
+    document.trailer.remove(b"Encrypt");
+    Ok(())
+}
+
+/// Parse a single direct object.
+///
+/// lopdf only exposes its parser for content streams, so the object
+/// is parsed as sole operand of a dummy operator.
+fn direct_object(bytes: &[u8]) -> Option<Object> {
+    let mut input = bytes.to_vec();
+    input.extend_from_slice(b" x");
+    Content::decode(&input)
+        .ok()?
+        .operations
+        .into_iter()
+        .next()?
+        .operands
+        .into_iter()
+        .next()
+}
+
+/// Dictionary and raw content of the last definition of stream `id` in `buffer`.
+fn raw_stream(document: &Document, buffer: &[u8], id: ObjectId) -> Option<(Dictionary, Vec<u8>)> {
+    let header = format!("{} {} obj", id.0, id.1);
+    let header = header.as_bytes();
+    let start = (0..buffer.len().checked_sub(header.len())?)
+        .rev()
+        .find(|&pos| {
+            buffer[pos..].starts_with(header) && (pos == 0 || buffer[pos - 1].is_ascii_whitespace())
+        })?
+        + header.len();
+    let keyword = find(&buffer[start..], b"stream")? + start;
+    let dict = match direct_object(&buffer[start..keyword])? {
+        Object::Dictionary(dict) => dict,
+        _ => return None,
+    };
+
+    let mut begin = keyword + b"stream".len();
+    if buffer.get(begin) == Some(&b'\r') {
+        begin += 1;
+    }
+    if buffer.get(begin) == Some(&b'\n') {
+        begin += 1;
+    }
+    let length = match dict.get(b"Length") {
+        Ok(Object::Integer(length)) => Some(*length as usize),
+        Ok(Object::Reference(id)) => document
+            .get_object(*id)
+            .and_then(Object::as_i64)
+            .ok()
+            .map(|length| length as usize),
+        _ => None,
+    };
+    let content = match length {
+        Some(length) => buffer.get(begin..begin + length)?,
+        None => {
+            let content = &buffer[begin..begin + find(&buffer[begin..], b"endstream")?];
+            let content = content.strip_suffix(b"\n").unwrap_or(content);
+            content.strip_suffix(b"\r").unwrap_or(content)
+        }
+    };
+    Some((dict, content.to_vec()))
+}
+
+/// The objects contained in a decompressed object stream.
+fn object_stream_members(stream: &Stream) -> Option<Vec<(ObjectId, Object)>> {
+    let first = stream.dict.get(b"First").and_then(Object::as_i64).ok()? as usize;
+    let index = std::str::from_utf8(stream.content.get(..first)?).ok()?;
+    let index = index
+        .split_whitespace()
+        .map(|number| number.parse::<usize>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let offsets = index
+        .chunks_exact(2)
+        .map(|pair| (pair[0] as u32, first + pair[1]))
+        .collect::<Vec<_>>();
+
+    let mut members = Vec::with_capacity(offsets.len());
+    for (idx, (number, offset)) in offsets.iter().enumerate() {
+        let end = offsets
+            .get(idx + 1)
+            .map(|(_, end)| *end)
+            .unwrap_or(stream.content.len());
+        let object = direct_object(stream.content.get(*offset..end)?)?;
+        members.push(((*number, 0), object));
+    }
+    Some(members)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// The first element of the file identifier, which is created if missing.
+fn file_identifier(document: &mut Document) -> Result<Vec<u8>> {
+    let existing = document
+        .trailer
+        .get(b"ID")
+        .and_then(Object::as_array)
+        .ok()
+        .and_then(|id| id.first())
+        .and_then(|id| id.as_str().ok())
+        .map(|id| id.to_vec());
+    if let Some(id) = existing {
+        return Ok(id);
+    }
+    let id = random_bytes(16)?;
+    document.trailer.set(
+        "ID",
+        vec![
+            Object::String(id.clone(), StringFormat::Hexadecimal),
+            Object::String(id.clone(), StringFormat::Hexadecimal),
+        ],
+    );
+    Ok(id)
+}
+
+fn object_key(algorithm: EncryptionAlgorithm, file_key: &[u8], id: ObjectId) -> Result<Vec<u8>> {
+    match algorithm {
+        EncryptionAlgorithm::Aes128 => {
+            let mut input = file_key.to_vec();
+            input.extend_from_slice(&id.0.to_le_bytes()[..3]);
+            input.extend_from_slice(&id.1.to_le_bytes()[..2]);
+            input.extend_from_slice(b"sAlT");
+            Ok(md5(&input)?[..16].to_vec())
+        }
+        EncryptionAlgorithm::Aes256 => Ok(file_key.to_vec()),
+    }
+}
+
+fn encrypt_object(object: &mut Object, key: &[u8]) -> Result<()> {
+    match object {
+        Object::String(content, format) => {
+            *content = aes_encrypt(key, content)?;
+            *format = StringFormat::Hexadecimal;
+        }
+        Object::Array(array) => {
+            for item in array.iter_mut() {
+                encrypt_object(item, key)?;
+            }
+        }
+        Object::Dictionary(dict) => encrypt_dictionary(dict, key)?,
+        Object::Stream(stream) => {
+            encrypt_dictionary(&mut stream.dict, key)?;
+            let content = aes_encrypt(key, &stream.content)?;
+            stream.set_content(content);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn encrypt_dictionary(dict: &mut Dictionary, key: &[u8]) -> Result<()> {
+    // the signature value is computed over the encrypted document
+    let signature = dict.type_is(b"Sig");
+    for (name, value) in dict.iter_mut() {
+        if signature && name == b"Contents" {
+            continue;
+        }
+        encrypt_object(value, key)?;
+    }
+    Ok(())
+}
+
+/// Encrypt with a random initialization vector, which is prepended.
+fn aes_encrypt(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let cipher = match key.len() {
+        16 => Cipher::aes_128_cbc(),
+        _ => Cipher::aes_256_cbc(),
+    };
+    let mut out = random_bytes(16)?;
+    let encrypted = openssl::symm::encrypt(cipher, key, Some(&out), data)?;
+    out.extend(encrypted);
+    Ok(out)
+}
+
+fn aes_no_padding(
+    mode: Mode,
+    cipher: Cipher,
+    key: &[u8],
+    iv: Option<&[u8]>,
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    let mut crypter = Crypter::new(cipher, mode, key, iv)?;
+    crypter.pad(false);
+    let mut out = vec![0u8; data.len() + cipher.block_size()];
+    let mut n = crypter.update(data, &mut out)?;
+    n += crypter.finalize(&mut out[n..])?;
+    out.truncate(n);
+    Ok(out)
+}
+
+fn random_bytes(n: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0u8; n];
+    rand_bytes(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn md5(data: &[u8]) -> Result<Vec<u8>> {
+    Ok(hash(MessageDigest::md5(), data)?.to_vec())
+}
+
+/// Characters of `PDFDocEncoding` which differ from latin1, by their code.
+const PDF_DOC_ENCODING: [(u8, char); 40] = [
+    (0x18, '˘'),
+    (0x19, 'ˇ'),
+    (0x1A, 'ˆ'),
+    (0x1B, '˙'),
+    (0x1C, '˝'),
+    (0x1D, '˛'),
+    (0x1E, '˚'),
+    (0x1F, '˜'),
+    (0x80, '•'),
+    (0x81, '†'),
+    (0x82, '‡'),
+    (0x83, '…'),
+    (0x84, '—'),
+    (0x85, '–'),
+    (0x86, 'ƒ'),
+    (0x87, '⁄'),
+    (0x88, '‹'),
+    (0x89, '›'),
+    (0x8A, '−'),
+    (0x8B, '‰'),
+    (0x8C, '„'),
+    (0x8D, '“'),
+    (0x8E, '”'),
+    (0x8F, '‘'),
+    (0x90, '’'),
+    (0x91, '‚'),
+    (0x92, '™'),
+    (0x93, 'ﬁ'),
+    (0x94, 'ﬂ'),
+    (0x95, 'Ł'),
+    (0x96, 'Œ'),
+    (0x97, 'Š'),
+    (0x98, 'Ÿ'),
+    (0x99, 'Ž'),
+    (0x9A, 'ı'),
+    (0x9B, 'ł'),
+    (0x9C, 'œ'),
+    (0x9D, 'š'),
+    (0x9E, 'ž'),
+    (0xA0, '€'),
+];
+
+/// Passwords for `R 4` and below are `PDFDocEncoding` encoded, which
+/// matches latin1 for most printable characters.
+fn pdf_doc_encoded(password: &str) -> Result<Vec<u8>> {
+    password
+        .chars()
+        .map(|c| {
+            if let Some((code, _)) = PDF_DOC_ENCODING.iter().find(|(_, special)| *special == c) {
+                return Ok(*code);
+            }
+            match c as u32 {
+                code @ (0x20..=0x7E | 0xA1..=0xAC | 0xAE..=0xFF) => Ok(code as u8),
+                _ => bail!(
+                    "Password character {:?} can not be encoded for `aes-128`, consider `aes-256`",
+                    c
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Passwords for `R 6` are `UTF-8` encoded and limited to 127 bytes.
+fn utf8_password(password: &str) -> &[u8] {
+    let bytes = password.as_bytes();
+    &bytes[..bytes.len().min(127)]
+}
+
+fn pad_password(password: &[u8]) -> Vec<u8> {
+    password
+        .iter()
+        .chain(PADDING.iter())
+        .take(32)
+        .copied()
+        .collect()
+}
+
+fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut s = (0..=255u8).collect::<Vec<u8>>();
+    let mut j = 0u8;
+    for i in 0..256 {
+        j = j.wrapping_add(s[i]).wrapping_add(key[i % key.len()]);
+        s.swap(i, j as usize);
+    }
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(s[i as usize]);
+            s.swap(i as usize, j as usize);
+            byte ^ s[s[i as usize].wrapping_add(s[j as usize]) as usize]
+        })
+        .collect()
+}
+
+/// Apply RC4 20 times with the key xored with the round number.
+fn rc4_rounds(key: &[u8], data: &[u8]) -> Vec<u8> {
+    (0..20u8).fold(data.to_vec(), |data, round| {
+        let key = key.iter().map(|b| b ^ round).collect::<Vec<u8>>();
+        rc4(&key, &data)
+    })
+}
+
+/// Undo [`rc4_rounds`] by applying the rounds in reverse order.
+fn rc4_rounds_reversed(key: &[u8], data: &[u8]) -> Vec<u8> {
+    (0..20u8).rev().fold(data.to_vec(), |data, round| {
+        let key = key.iter().map(|b| b ^ round).collect::<Vec<u8>>();
+        rc4(&key, &data)
+    })
+}
+
+/// The RC4 key derived from the owner password, part of algorithm 3.
+fn owner_key_legacy(owner: &[u8], revision: i64, length: usize) -> Result<Vec<u8>> {
+    let mut digest = md5(&pad_password(owner))?;
+    if revision >= 3 {
+        for _ in 0..50 {
+            digest = md5(&digest)?;
+        }
+    }
+    digest.truncate(length);
+    Ok(digest)
+}
+
+/// Algorithm 3, the `O` entry for `R 2` to `R 4`.
+fn owner_entry_legacy(owner: &[u8], user: &[u8], revision: i64, length: usize) -> Result<Vec<u8>> {
+    let key = owner_key_legacy(owner, revision, length)?;
+    match revision {
+        2 => Ok(rc4(&key, &pad_password(user))),
+        _ => Ok(rc4_rounds(&key, &pad_password(user))),
+    }
+}
+
+/// Algorithm 2, the file encryption key for `R 2` to `R 4`.
+fn file_key_legacy(
+    user: &[u8],
+    o: &[u8],
+    p: i32,
+    id: &[u8],
+    revision: i64,
+    encrypt_metadata: bool,
+    length: usize,
+) -> Result<Vec<u8>> {
+    let mut input = pad_password(user);
+    input.extend_from_slice(&o[..o.len().min(32)]);
+    input.extend_from_slice(&p.to_le_bytes());
+    input.extend_from_slice(id);
+    if revision >= 4 && !encrypt_metadata {
+        input.extend_from_slice(&[0xFF; 4]);
+    }
+    let mut digest = md5(&input)?;
+    if revision >= 3 {
+        for _ in 0..50 {
+            digest = md5(&digest[..length])?;
+        }
+    }
+    digest.truncate(length);
+    Ok(digest)
+}
+
+/// Algorithms 4 and 5, the `U` entry for `R 2` to `R 4`.
+fn user_entry_legacy(file_key: &[u8], id: &[u8], revision: i64) -> Result<Vec<u8>> {
+    if revision == 2 {
+        return Ok(rc4(file_key, &PADDING));
+    }
+    let mut input = PADDING.to_vec();
+    input.extend_from_slice(id);
+    let mut u = rc4_rounds(file_key, &md5(&input)?);
+    // arbitrary padding to 32 bytes
+    u.extend_from_slice(&[0u8; 16]);
+    Ok(u)
+}
+
+/// Algorithm 2.B, the hash used for `R 6`.
+fn hash_r6(password: &[u8], salt: &[u8], user_entry: &[u8]) -> Result<Vec<u8>> {
+    let input = [password, salt, user_entry].concat();
+    let mut k = hash(MessageDigest::sha256(), &input)?.to_vec();
+    let mut round = 0usize;
+    loop {
+        let k1 = [password, &k, user_entry].concat().repeat(64);
+        let e = aes_no_padding(
+            Mode::Encrypt,
+            Cipher::aes_128_cbc(),
+            &k[..16],
+            Some(&k[16..32]),
+            &k1,
+        )?;
+        let digest = match e[..16].iter().map(|b| *b as u32).sum::<u32>() % 3 {
+            0 => MessageDigest::sha256(),
+            1 => MessageDigest::sha384(),
+            _ => MessageDigest::sha512(),
+        };
+        k = hash(digest, &e)?.to_vec();
+        round += 1;
+        let last = *e.last().expect("Input is never empty. qed") as usize;
+        if round >= 64 && last + 32 <= round {
+            break;
+        }
+    }
+    k.truncate(32);
+    Ok(k)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rc4_reference() {
+        // test vector from the original RC4 publication
+        assert_eq!(
+            rc4(b"Key", b"Plaintext"),
+            vec![0xBB, 0xF3, 0x16, 0xE8, 0xD9, 0x40, 0xAF, 0x0A, 0xD3]
+        );
+    }
+
+    #[test]
+    fn permissions() {
+        let none = Permissions {
+            print: false,
+            modify: false,
+            copy: false,
+            annotate: false,
+        };
+        assert_eq!(none.bits() as u32, 0xFFFF_F2C0);
+        let print = Permissions {
+            print: true,
+            ..none
+        };
+        assert_eq!(print.bits() as u32 & 0b100, 0b100);
+    }
+
+    const CONTENT: &[u8] = b"BT /F1 12 Tf 72 720 Td (Total 5,00 EUR) Tj ET";
+    const SIGNATURE: &[u8] = &[0x30, 0x82, 0x00, 0x00];
+
+    /// A single page document with a signature dictionary, along with the
+    /// ids of the content stream and the signature.
+    fn document() -> (Document, ObjectId, ObjectId) {
+        let mut document = Document::with_version("1.5");
+        let pages = document.new_object_id();
+        let content = document.add_object(Stream::new(dictionary! {}, CONTENT.to_vec()));
+        let page = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages,
+            "Contents" => content,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        });
+        document.objects.insert(
+            pages,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page.into()],
+                "Count" => 1,
+            }),
+        );
+        let signature = document.add_object(dictionary! {
+            "Type" => "Sig",
+            "Reason" => Object::string_literal("Reimbursement"),
+            "Contents" => Object::String(SIGNATURE.to_vec(), StringFormat::Hexadecimal),
+        });
+        let catalog = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages,
+        });
+        document.trailer.set("Root", catalog);
+        (document, content, signature)
+    }
+
+    fn signature_entry<'a>(document: &'a Document, signature: ObjectId, key: &[u8]) -> &'a [u8] {
+        document
+            .get_dictionary(signature)
+            .and_then(|dict| dict.get(key))
+            .and_then(Object::as_str)
+            .unwrap()
+    }
+
+    /// Save and load the encrypted `document`, then decrypt it with `password`.
+    fn reopen(document: &mut Document, password: &str) -> Result<Document> {
+        let mut buffer = Vec::new();
+        document.save_to(&mut buffer)?;
+        let mut reopened = Document::load_mem(&buffer).unwrap();
+        assert!(is_encrypted(&reopened));
+        decrypt(&mut reopened, &buffer, &[password.to_owned()])?;
+        Ok(reopened)
+    }
+
+    fn round_trip(encrypt: impl Fn(&mut Document)) {
+        for password in ["user", "owner"] {
+            let (mut document, content, signature) = document();
+            encrypt(&mut document);
+            // the signature value is computed over the encrypted document
+            assert_eq!(
+                signature_entry(&document, signature, b"Contents"),
+                SIGNATURE
+            );
+            assert_ne!(
+                signature_entry(&document, signature, b"Reason"),
+                b"Reimbursement"
+            );
+
+            let decrypted = reopen(&mut document, password).unwrap();
+            assert!(!is_encrypted(&decrypted));
+            let stream = decrypted.get_object(content).unwrap().as_stream().unwrap();
+            assert_eq!(stream.content, CONTENT);
+            assert_eq!(
+                signature_entry(&decrypted, signature, b"Contents"),
+                SIGNATURE
+            );
+            assert_eq!(
+                signature_entry(&decrypted, signature, b"Reason"),
+                b"Reimbursement"
+            );
+        }
+        let (mut document, _, _) = document();
+        encrypt(&mut document);
+        assert!(reopen(&mut document, "guess").is_err());
+    }
+
+    fn permissions_all() -> Permissions {
+        Permissions {
+            print: true,
+            modify: true,
+            copy: true,
+            annotate: true,
+        }
+    }
+
+    #[test]
+    fn aes_round_trip() {
+        for algorithm in [EncryptionAlgorithm::Aes128, EncryptionAlgorithm::Aes256] {
+            round_trip(|document| {
+                encrypt(
+                    document,
+                    algorithm,
+                    "user",
+                    Some("owner"),
+                    permissions_all(),
+                )
+                .unwrap()
+            });
+        }
+    }
+
+    #[test]
+    fn pdf_doc_encoded_passwords() {
+        assert_eq!(
+            pdf_doc_encoded("€uro–2021").unwrap(),
+            b"\xA0uro\x852021".to_vec()
+        );
+        assert_eq!(pdf_doc_encoded("Grüße").unwrap(), b"Gr\xFC\xDFe".to_vec());
+        assert!(pdf_doc_encoded("пароль").is_err());
+
+        let (mut latin, content, _) = document();
+        encrypt(
+            &mut latin,
+            EncryptionAlgorithm::Aes128,
+            "€uro2021",
+            Some("owner"),
+            permissions_all(),
+        )
+        .unwrap();
+        let decrypted = reopen(&mut latin, "€uro2021").unwrap();
+        let stream = decrypted.get_object(content).unwrap().as_stream().unwrap();
+        assert_eq!(stream.content, CONTENT);
+        assert!(reopen(&mut latin, "uro2021").is_err());
+
+        // never silently encrypted with an empty user password instead
+        let (mut cyrillic, _, _) = document();
+        let err = encrypt(
+            &mut cyrillic,
+            EncryptionAlgorithm::Aes128,
+            "пароль",
+            Some("owner"),
+            permissions_all(),
+        );
+        assert!(err.is_err());
+        encrypt(
+            &mut cyrillic,
+            EncryptionAlgorithm::Aes256,
+            "пароль",
+            Some("owner"),
+            permissions_all(),
+        )
+        .unwrap();
+        assert!(reopen(&mut cyrillic, "пароль").is_ok());
+    }
+
+    #[test]
+    fn rc4_round_trip() {
+        // only decryption is supported, RC4 is symmetric though
+        round_trip(|document| {
+            let id = file_identifier(document).unwrap();
+            let p = permissions_all().bits();
+            let user = pdf_doc_encoded("user").unwrap();
+            let o = owner_entry_legacy(&pdf_doc_encoded("owner").unwrap(), &user, 3, 16).unwrap();
+            let file_key = file_key_legacy(&user, &o, p, &id, 3, true, 16).unwrap();
+            let u = user_entry_legacy(&file_key, &id, 3).unwrap();
+            let handler = SecurityHandler {
+                revision: 3,
+                length: 16,
+                o: o.clone(),
+                u: u.clone(),
+                oe: Vec::new(),
+                ue: Vec::new(),
+                p,
+                id,
+                encrypt_metadata: true,
+                streams: CryptMethod::Rc4,
+                strings: CryptMethod::Rc4,
+            };
+            for (id, object) in document.objects.iter_mut() {
+                handler.decrypt_object(object, &file_key, *id).unwrap();
+            }
+            let encrypt = document.add_object(dictionary! {
+                "Filter" => "Standard",
+                "V" => 2,
+                "R" => 3,
+                "Length" => 128,
+                "O" => Object::String(o, StringFormat::Hexadecimal),
+                "U" => Object::String(u, StringFormat::Hexadecimal),
+                "P" => p,
+            });
+            document.trailer.set("Encrypt", encrypt);
+        });
+    }
+}
@@ -0,0 +1,318 @@
+//! Text of receipt pages, as shown by their content streams.
+//!
+//! Glyph codes are mapped via the `ToUnicode` cmap of the font if there
+//! is one, single byte codes of simple fonts are taken as latin1 otherwise.
+//! The layout is only approximated, text moving to another line starts a
+//! new line, any other movement inserts a space.
+
+use std::collections::{BTreeMap, HashMap};
+
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+
+/// Horizontal adjustments of `TJ` arrays beyond this, in thousandths of
+/// the font size, are gaps between words.
+const WORD_GAP: f64 = 200.;
+
+/// The text of all pages of `document`, in page order.
+pub fn page_texts(document: &Document) -> Vec<String> {
+    document
+        .get_pages()
+        .into_values()
+        .map(|page| page_text(document, page))
+        .collect()
+}
+
+fn page_text(document: &Document, page: ObjectId) -> String {
+    let content = match document
+        .get_page_content(page)
+        .ok()
+        .and_then(|content| Content::decode(&content).ok())
+    {
+        Some(content) => content,
+        None => {
+            log::debug!("Content of page {:?} could not be decoded", page);
+            return String::new();
+        }
+    };
+    let fonts = document
+        .get_page_fonts(page)
+        .into_iter()
+        .map(|(name, font)| (name, FontDecoder::new(document, font)))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut text = String::new();
+    let mut font: Option<&FontDecoder> = None;
+    let mut line_y: Option<f64> = None;
+    for operation in content.operations.iter() {
+        let operands = &operation.operands;
+        match operation.operator.as_str() {
+            "Tf" => {
+                font = operands
+                    .first()
+                    .and_then(|name| name.as_name().ok())
+                    .and_then(|name| fonts.get(name));
+            }
+            "Td" | "TD" => {
+                let ty = operands.get(1).map_or(0., number);
+                separate(&mut text, ty != 0.);
+            }
+            "Tm" => {
+                let y = operands.get(5).map_or(0., number);
+                separate(&mut text, line_y.is_some_and(|line_y| line_y != y));
+                line_y = Some(y);
+            }
+            "T*" => separate(&mut text, true),
+            "Tj" | "'" | "\"" => {
+                if operation.operator != "Tj" {
+                    separate(&mut text, true);
+                }
+                if let Some(Object::String(bytes, _)) = operands.last() {
+                    text.push_str(&decode(font, bytes));
+                }
+            }
+            "TJ" => {
+                let elements = operands.first().and_then(|array| array.as_array().ok());
+                for element in elements.into_iter().flatten() {
+                    match element {
+                        Object::String(bytes, _) => text.push_str(&decode(font, bytes)),
+                        adjustment if -number(adjustment) > WORD_GAP => separate(&mut text, false),
+                        _ => {}
+                    }
+                }
+            }
+            "ET" => separate(&mut text, false),
+            _ => {}
+        }
+    }
+    text
+}
+
+fn number(object: &Object) -> f64 {
+    object.as_float().unwrap_or(0.)
+}
+
+/// Separate the following text by a space or a new line.
+fn separate(text: &mut String, new_line: bool) {
+    match text.chars().last() {
+        None | Some('\n') => {}
+        Some(' ') if !new_line => {}
+        Some(' ') => {
+            text.pop();
+            text.push('\n');
+        }
+        Some(_) => text.push(if new_line { '\n' } else { ' ' }),
+    }
+}
+
+fn decode(font: Option<&FontDecoder>, bytes: &[u8]) -> String {
+    match font {
+        Some(font) => font.decode(bytes),
+        None => bytes.iter().map(|&byte| byte as char).collect(),
+    }
+}
+
+/// Maps the glyph codes of a font to text.
+struct FontDecoder {
+    /// Number of bytes per glyph code.
+    code_len: usize,
+    to_unicode: HashMap<u32, String>,
+}
+
+impl FontDecoder {
+    fn new(document: &Document, font: &Dictionary) -> Self {
+        // composite fonts are practically always used with two byte codes
+        let code_len = match font.get(b"Subtype").and_then(Object::as_name) {
+            Ok(b"Type0") => 2,
+            _ => 1,
+        };
+        let to_unicode = font
+            .get(b"ToUnicode")
+            .and_then(Object::as_reference)
+            .and_then(|id| document.get_object(id))
+            .and_then(Object::as_stream)
+            .map(|stream| {
+                let content = stream
+                    .decompressed_content()
+                    .unwrap_or_else(|_| stream.content.clone());
+                parse_cmap(&String::from_utf8_lossy(&content))
+            })
+            .unwrap_or_default();
+        Self {
+            code_len,
+            to_unicode,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> String {
+        bytes
+            .chunks(self.code_len)
+            .filter_map(|bytes| {
+                let code = code(bytes);
+                match self.to_unicode.get(&code) {
+                    Some(text) => Some(text.clone()),
+                    // without a mapping, only single byte codes are meaningful,
+                    // the euro sign is the one difference to latin1 which matters
+                    None if self.code_len == 1 && code == 0x80 => Some("€".to_owned()),
+                    None if self.code_len == 1 => char::from_u32(code).map(String::from),
+                    None => None,
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    Hex(Vec<u8>),
+    Open,
+    Close,
+    Word(&'a str),
+}
+
+fn cmap_tokens(cmap: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = cmap;
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_whitespace() {
+            rest = rest.trim_start();
+        } else if c == '<' && !rest.starts_with("<<") {
+            let end = rest.find('>').unwrap_or(rest.len());
+            let digits = rest[1..end]
+                .chars()
+                .filter(char::is_ascii_hexdigit)
+                .collect::<Vec<_>>();
+            let bytes = digits
+                .chunks(2)
+                .filter_map(|pair| {
+                    let pair = pair.iter().collect::<String>();
+                    u8::from_str_radix(&format!("{:0<2}", pair), 16).ok()
+                })
+                .collect();
+            tokens.push(Token::Hex(bytes));
+            rest = rest.get(end + 1..).unwrap_or_default();
+        } else if c == '[' || c == ']' {
+            tokens.push(if c == '[' { Token::Open } else { Token::Close });
+            rest = &rest[1..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_ascii_whitespace() || c == '<' || c == '[' || c == ']')
+                .unwrap_or(rest.len())
+                .max(c.len_utf8());
+            tokens.push(Token::Word(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    tokens
+}
+
+fn code(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0u32, |code, &byte| code << 8 | byte as u32)
+}
+
+fn utf16(bytes: &[u8]) -> String {
+    let units = bytes
+        .chunks(2)
+        .map(|pair| code(pair) as u16)
+        .collect::<Vec<u16>>();
+    String::from_utf16_lossy(&units)
+}
+
+/// Parse the `bfchar` and `bfrange` mappings of a `ToUnicode` cmap.
+fn parse_cmap(cmap: &str) -> HashMap<u32, String> {
+    let mut map = HashMap::new();
+    let tokens = cmap_tokens(cmap);
+    let mut idx = 0;
+    let mut section = None;
+    while idx < tokens.len() {
+        match (&tokens[idx], section) {
+            (Token::Word(word), _) => {
+                section = match *word {
+                    "beginbfchar" => Some("bfchar"),
+                    "beginbfrange" => Some("bfrange"),
+                    _ => None,
+                };
+                idx += 1;
+            }
+            (Token::Hex(src), Some("bfchar")) => {
+                if let Some(Token::Hex(dst)) = tokens.get(idx + 1) {
+                    map.insert(code(src), utf16(dst));
+                }
+                idx += 2;
+            }
+            (Token::Hex(low), Some("bfrange")) => {
+                let high = match tokens.get(idx + 1) {
+                    Some(Token::Hex(high)) => code(high),
+                    _ => break,
+                };
+                let low = code(low);
+                idx += 2;
+                match tokens.get(idx) {
+                    // consecutive codes map to consecutive characters
+                    Some(Token::Hex(dst)) if !dst.is_empty() => {
+                        let mut units = dst
+                            .chunks(2)
+                            .map(|pair| code(pair) as u16)
+                            .collect::<Vec<u16>>();
+                        for code in low..=high.min(low + 0xffff) {
+                            map.insert(code, String::from_utf16_lossy(&units));
+                            if let Some(last) = units.last_mut() {
+                                *last = last.wrapping_add(1);
+                            }
+                        }
+                        idx += 1;
+                    }
+                    Some(Token::Open) => {
+                        idx += 1;
+                        let mut code = low;
+                        while let Some(Token::Hex(dst)) = tokens.get(idx) {
+                            map.insert(code, utf16(dst));
+                            code += 1;
+                            idx += 1;
+                        }
+                        // skip the closing bracket
+                        idx += 1;
+                    }
+                    _ => idx += 1,
+                }
+            }
+            _ => idx += 1,
+        }
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cmap() {
+        let map = parse_cmap(
+            r#"/CIDInit /ProcSet findresource begin
+12 dict begin
+begincmap
+1 begincodespacerange
+<0000> <FFFF>
+endcodespacerange
+2 beginbfchar
+<0003> <0020>
+<0011> <002C>
+endbfchar
+2 beginbfrange
+<0013> <001C> <0030>
+<005E> <005F> [<00660066> <20AC>]
+endbfrange
+endcmap"#,
+        );
+        assert_eq!(map.get(&0x03).map(String::as_str), Some(" "));
+        assert_eq!(map.get(&0x11).map(String::as_str), Some(","));
+        assert_eq!(map.get(&0x13).map(String::as_str), Some("0"));
+        assert_eq!(map.get(&0x1c).map(String::as_str), Some("9"));
+        assert_eq!(map.get(&0x5e).map(String::as_str), Some("ff"));
+        assert_eq!(map.get(&0x5f).map(String::as_str), Some("€"));
+        assert_eq!(map.len(), 14);
+    }
+}
@@ -0,0 +1,112 @@
+//! Fingerprints of receipts, to detect the same receipt being submitted
+//! twice, within a report or across reports.
+//!
+//! Files are identified by a digest of their content, images additionally
+//! by a perceptual hash, which survives re-encoding, scaling and slightly
+//! different scans of the same paper.
+
+use printpdf::image::{imageops::FilterType, DynamicImage};
+use serde::{Deserialize, Serialize};
+
+use crate::types::PageRange;
+
+/// Maximum number of differing bits of perceptual hashes of the same image.
+const MAX_DISTANCE: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// Hex encoded sha256 of the file content, suffixed by the selected pages.
+    pub digest: String,
+    /// Difference hash of image receipts.
+    pub perceptual: Option<u64>,
+}
+
+impl Fingerprint {
+    /// Fingerprint of the file `content`, `perceptual` for images.
+    pub fn new(content: &[u8], perceptual: Option<u64>) -> Self {
+        let digest = openssl::sha::sha256(content)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Self { digest, perceptual }
+    }
+
+    /// Restrict the fingerprint to `pages` of the file, different pages
+    /// of the same file are different receipts.
+    pub fn select_pages(&mut self, pages: &[PageRange]) {
+        if pages.is_empty() {
+            return;
+        }
+        let pages = pages
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("+");
+        self.digest = format!("{}#pages={}", self.digest, pages);
+        // the hash is one of the first frame only
+        self.perceptual = None;
+    }
+
+    /// Whether both fingerprints are of the very same file content.
+    pub fn identical(&self, other: &Self) -> bool {
+        self.digest == other.digest
+    }
+
+    /// Whether both fingerprints are likely of the same receipt, identical
+    /// or similar looking images.
+    pub fn matches(&self, other: &Self) -> bool {
+        if self.identical(other) {
+            return true;
+        }
+        match (self.perceptual, other.perceptual) {
+            (Some(a), Some(b)) => (a ^ b).count_ones() <= MAX_DISTANCE,
+            _ => false,
+        }
+    }
+}
+
+/// Difference hash of `image`, one bit per pair of horizontally adjacent
+/// pixels of a 9x8 thumbnail, set if the left one is brighter.
+///
+/// Images without any structure, i.e. blank pages, have no meaningful hash.
+pub fn perceptual_hash(image: &DynamicImage) -> Option<u64> {
+    let thumbnail = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = thumbnail.get_pixel(x, y)[0] > thumbnail.get_pixel(x + 1, y)[0];
+            hash = hash << 1 | brighter as u64;
+        }
+    }
+    Some(hash).filter(|hash| *hash != 0 && *hash != u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use printpdf::image::{GrayImage, Luma};
+
+    #[test]
+    fn perceptual() {
+        let waves = GrayImage::from_fn(180, 160, |x, y| {
+            let phase = x as f64 / 15. + y as f64 / 25.;
+            Luma([(127. + 100. * phase.sin()) as u8])
+        });
+        let image = DynamicImage::ImageLuma8(waves);
+        let scaled = image.resize_exact(90, 80, FilterType::Triangle);
+        let a = Fingerprint::new(b"a", perceptual_hash(&image));
+        let b = Fingerprint::new(b"b", perceptual_hash(&scaled));
+        assert!(a.perceptual.is_some());
+        assert!(a.matches(&b));
+        assert!(!a.identical(&b));
+
+        let mut inverted = image.clone();
+        inverted.invert();
+        let c = Fingerprint::new(b"c", perceptual_hash(&inverted));
+        assert!(!a.matches(&c));
+        assert!(a.matches(&Fingerprint::new(b"a", None)));
+
+        let blank = DynamicImage::ImageLuma8(GrayImage::from_pixel(10, 10, Luma([255])));
+        assert_eq!(perceptual_hash(&blank), None);
+    }
+}
@@ -0,0 +1,299 @@
+//! Decoding of images with several frames, i.e. multi-page TIFFs from
+//! office scanners or GIFs, each frame becomes a page of its own.
+
+use crate::errors::*;
+
+use printpdf::image::{
+    self, codecs::gif::GifDecoder, AnimationDecoder, DynamicImage, GrayAlphaImage, GrayImage,
+    ImageBuffer, RgbImage, RgbaImage,
+};
+use tiff::decoder::{Decoder as TiffDecoder, DecodingResult};
+use tiff::tags::Tag;
+use tiff::ColorType;
+
+use std::io::Cursor;
+
+/// Decode all frames of the image `bytes`, as identified by the file extension `ext`.
+pub(crate) fn decode(bytes: &[u8], ext: &str) -> Result<Vec<DynamicImage>> {
+    match ext {
+        "tif" | "tiff" => tiff_frames(bytes),
+        "gif" => gif_frames(bytes),
+        _ => {
+            let reader = image::io::Reader::new(Cursor::new(bytes));
+            let reader = image::io::Reader::with_guessed_format(reader)?;
+            Ok(vec![reader.decode()?])
+        }
+    }
+}
+
+fn tiff_frames(bytes: &[u8]) -> Result<Vec<DynamicImage>> {
+    let mut decoder =
+        TiffDecoder::new(Cursor::new(bytes)).map_err(|e| eyre!("Failed to read tiff: {}", e))?;
+    let mut frames = vec![tiff_frame(&mut decoder, bytes)?];
+    while decoder.more_images() {
+        decoder
+            .next_image()
+            .map_err(|e| eyre!("Failed to read tiff page {}: {}", frames.len() + 1, e))?;
+        frames.push(tiff_frame(&mut decoder, bytes)?);
+    }
+    Ok(frames)
+}
+
+/// Decode the current frame of `decoder`, reading the tiff `bytes`.
+fn tiff_frame(decoder: &mut TiffDecoder<Cursor<&[u8]>>, bytes: &[u8]) -> Result<DynamicImage> {
+    let (width, height) = decoder
+        .dimensions()
+        .map_err(|e| eyre!("Failed to read tiff dimensions: {}", e))?;
+    let color = decoder
+        .colortype()
+        .map_err(|e| eyre!("Failed to read tiff color type: {}", e))?;
+    let compression = decoder
+        .find_tag_unsigned::<u16>(Tag::Compression)
+        .map_err(|e| eyre!("Failed to read tiff compression: {}", e))?
+        .unwrap_or(1);
+    // common for fax and office scanner output, but unsupported by the decoder
+    if (2..=4).contains(&compression) {
+        bail!("CCITT fax compressed tiffs are not supported, convert them first, i.e. `tiffcp -c packbits in.tif out.tif`");
+    }
+    if let ColorType::Gray(bits @ (1 | 2 | 4)) = color {
+        return packed_gray_frame(decoder, bytes, compression, width, height, bits)
+            .map(DynamicImage::ImageLuma8);
+    }
+    let data = decoder
+        .read_image()
+        .map_err(|e| eyre!("Failed to decode tiff: {}", e))?;
+    let frame = match (color, data) {
+        (ColorType::Gray(8), DecodingResult::U8(data)) => {
+            GrayImage::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+        }
+        (ColorType::GrayA(8), DecodingResult::U8(data)) => {
+            GrayAlphaImage::from_raw(width, height, data).map(DynamicImage::ImageLumaA8)
+        }
+        (ColorType::RGB(8), DecodingResult::U8(data)) => {
+            RgbImage::from_raw(width, height, data).map(DynamicImage::ImageRgb8)
+        }
+        (ColorType::RGBA(8), DecodingResult::U8(data)) => {
+            RgbaImage::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+        }
+        (ColorType::CMYK(8), DecodingResult::U8(data)) => {
+            let rgb = data
+                .chunks_exact(4)
+                .flat_map(|cmyk| {
+                    let white = 255 - cmyk[3] as u16;
+                    let channel = move |c: u8| ((255 - c as u16) * white / 255) as u8;
+                    [channel(cmyk[0]), channel(cmyk[1]), channel(cmyk[2])]
+                })
+                .collect::<Vec<u8>>();
+            RgbImage::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+        }
+        (ColorType::Gray(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16)
+        }
+        (ColorType::RGB(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16)
+        }
+        (ColorType::RGBA(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba16)
+        }
+        (color, _) => bail!("Unsupported tiff color type {:?}", color),
+    };
+    frame.ok_or_else(|| {
+        eyre!(
+            "Tiff page data does not match its size of {}x{}",
+            width,
+            height
+        )
+    })
+}
+
+/// Decode the current frame of bilevel or other gray scans with less than
+/// 8 bits per sample, which the tiff decoder rejects, from its uncompressed
+/// or PackBits compressed strips.
+fn packed_gray_frame(
+    decoder: &mut TiffDecoder<Cursor<&[u8]>>,
+    bytes: &[u8],
+    compression: u16,
+    width: u32,
+    height: u32,
+    bits: u8,
+) -> Result<GrayImage> {
+    let tag = |decoder: &mut TiffDecoder<Cursor<&[u8]>>, tag: Tag| {
+        decoder
+            .get_tag_u64_vec(tag)
+            .map_err(|e| eyre!("Failed to read tiff {:?}: {}", tag, e))
+    };
+    let offsets = tag(decoder, Tag::StripOffsets)?;
+    let counts = tag(decoder, Tag::StripByteCounts)?;
+    let white_is_zero = decoder
+        .find_tag_unsigned::<u16>(Tag::PhotometricInterpretation)
+        .map_err(|e| eyre!("Failed to read tiff photometric interpretation: {}", e))?
+        == Some(0);
+
+    let mut packed = Vec::new();
+    for (offset, count) in offsets.into_iter().zip(counts) {
+        let strip = usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(count).ok())
+            .and_then(|(offset, count)| bytes.get(offset..offset.checked_add(count)?))
+            .ok_or_else(|| eyre!("Tiff strip exceeds the file"))?;
+        match compression {
+            1 => packed.extend_from_slice(strip),
+            32773 => unpack_bits(strip, &mut packed),
+            compression => bail!(
+                "Unsupported compression {} of a tiff with {} bits per sample",
+                compression,
+                bits
+            ),
+        }
+    }
+
+    // rows are padded to full bytes
+    let (width, height, bits) = (width as usize, height as usize, bits as usize);
+    let stride = (width * bits).div_ceil(8);
+    if packed.len() < stride * height {
+        bail!(
+            "Tiff page data does not match its size of {}x{}",
+            width,
+            height
+        );
+    }
+    let max = (1u16 << bits) - 1;
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in packed.chunks_exact(stride).take(height) {
+        for x in 0..width {
+            let bit = x * bits;
+            let mut sample = (row[bit / 8] >> (8 - bits - bit % 8)) as u16 & max;
+            if white_is_zero {
+                sample = max - sample;
+            }
+            pixels.push((sample * 255 / max) as u8);
+        }
+    }
+    GrayImage::from_raw(width as u32, height as u32, pixels)
+        .ok_or_else(|| eyre!("Tiff page data does not match its size"))
+}
+
+/// Decompress the PackBits run length encoded `data` into `out`.
+fn unpack_bits(data: &[u8], out: &mut Vec<u8>) {
+    let mut idx = 0;
+    while let Some(&header) = data.get(idx) {
+        idx += 1;
+        match header as i8 {
+            -128 => {}
+            n @ 0..=127 => {
+                let end = (idx + n as usize + 1).min(data.len());
+                out.extend_from_slice(&data[idx..end]);
+                idx = end;
+            }
+            n => {
+                if let Some(&byte) = data.get(idx) {
+                    out.extend(std::iter::repeat_n(byte, (1 - n as i16) as usize));
+                }
+                idx += 1;
+            }
+        }
+    }
+}
+
+fn gif_frames(bytes: &[u8]) -> Result<Vec<DynamicImage>> {
+    let decoder = GifDecoder::new(Cursor::new(bytes))?;
+    let frames = decoder.into_frames().collect_frames()?;
+    Ok(frames
+        .into_iter()
+        .map(|frame| DynamicImage::ImageRgba8(frame.into_buffer()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use printpdf::image::GenericImageView;
+
+    #[test]
+    fn multi_page_tiff() {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = tiff::encoder::TiffEncoder::new(Cursor::new(&mut bytes)).unwrap();
+            encoder
+                .write_image::<tiff::encoder::colortype::RGB8>(4, 2, &[255u8; 4 * 2 * 3])
+                .unwrap();
+            encoder
+                .write_image::<tiff::encoder::colortype::Gray8>(3, 5, &[0u8; 3 * 5])
+                .unwrap();
+        }
+        let frames = decode(&bytes, "tif").unwrap();
+        let dimensions = frames
+            .iter()
+            .map(|frame| frame.dimensions())
+            .collect::<Vec<_>>();
+        assert_eq!(dimensions, vec![(4, 2), (3, 5)]);
+    }
+
+    /// A little endian tiff of 10x3 pixels with one bit per sample in two
+    /// strips, the first one with two rows.
+    fn bilevel_tiff(photometric: u16, compression: u16, strips: [&[u8]; 2]) -> Vec<u8> {
+        let mut bytes = b"II".to_vec();
+        bytes.extend_from_slice(&42u16.to_le_bytes());
+        bytes.extend_from_slice(&8u32.to_le_bytes());
+        let short = |tag: u16, value: u16| {
+            let mut entry = [tag.to_le_bytes(), 3u16.to_le_bytes()].concat();
+            entry.extend_from_slice(&1u32.to_le_bytes());
+            entry.extend_from_slice(&value.to_le_bytes());
+            entry.extend_from_slice(&[0, 0]);
+            entry
+        };
+        let longs = |tag: u16, offset: u32| {
+            let mut entry = [tag.to_le_bytes(), 4u16.to_le_bytes()].concat();
+            entry.extend_from_slice(&2u32.to_le_bytes());
+            entry.extend_from_slice(&offset.to_le_bytes());
+            entry
+        };
+        // header, entry count, 8 entries and the offset of the next directory
+        let data = 8 + 2 + 8 * 12 + 4;
+        let (first, second) = (strips[0].len() as u32, strips[1].len() as u32);
+        let arrays = data + first + second;
+        bytes.extend_from_slice(&8u16.to_le_bytes());
+        for entry in [
+            short(256, 10),
+            short(257, 3),
+            short(258, 1),
+            short(259, compression),
+            short(262, photometric),
+            longs(273, arrays),
+            short(278, 2),
+            longs(279, arrays + 8),
+        ] {
+            bytes.extend_from_slice(&entry);
+        }
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&strips.concat());
+        for value in [data, data + first, first, second] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn bilevel_tiff_frames() {
+        let plain: [&[u8]; 2] = [&[0b1000_0000, 0b0100_0000, 0xFF, 0xC0], &[0, 0]];
+        let packed: [&[u8]; 2] = [&[3, 0b1000_0000, 0b0100_0000, 0xFF, 0xC0], &[0xFF, 0]];
+        for (photometric, compression, strips, white) in [
+            (1u16, 1u16, plain, 255u8),
+            (0, 1, plain, 0),
+            (1, 32773, packed, 255),
+        ] {
+            let tiff = bilevel_tiff(photometric, compression, strips);
+            let frames = decode(&tiff, "tif").unwrap();
+            let frame = frames[0].to_luma8();
+            assert_eq!(frame.dimensions(), (10, 3));
+            let black = 255 - white;
+            assert_eq!(frame.get_pixel(0, 0).0, [white]);
+            assert_eq!(frame.get_pixel(1, 0).0, [black]);
+            assert_eq!(frame.get_pixel(9, 0).0, [white]);
+            assert_eq!(frame.get_pixel(5, 1).0, [white]);
+            assert_eq!(frame.get_pixel(9, 2).0, [black]);
+        }
+        let error = decode(&bilevel_tiff(0, 4, plain), "tif").unwrap_err();
+        assert!(error.to_string().contains("CCITT"));
+    }
+}
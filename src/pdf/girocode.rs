@@ -0,0 +1,164 @@
+//! EPC069-12 QR code, also known as GiroCode, with the credit transfer of
+//! the reimbursement, for the summary page.
+
+use super::constants::DIN_A4;
+use super::tabular::{text, Alignment};
+use super::types::*;
+use crate::errors::*;
+use crate::types::{BankInfo, Euro};
+
+use printpdf::{Greyscale, Line, PdfLayerReference};
+use qrcode::{EcLevel, QrCode};
+
+/// Upper bound of the payload defined by the standard.
+const MAX_PAYLOAD: usize = 331;
+const MAX_NAME: usize = 70;
+const MAX_REFERENCE: usize = 140;
+/// Edge length of the code, excluding the quiet zone.
+const SIZE: f64 = 95.;
+const CAPTION_SIZE: i32 = 7;
+
+/// Credit transfer to the employee.
+#[derive(Debug, Clone)]
+pub struct GiroCode {
+    pub name: String,
+    /// IBAN in electronic format, without spaces.
+    pub iban: String,
+    /// Optional within the EEA.
+    pub bic: Option<String>,
+    pub amount: Euro,
+    /// Unstructured remittance information.
+    pub reference: String,
+}
+
+impl GiroCode {
+    pub fn new(bankinfo: &BankInfo, amount: Euro, reference: impl AsRef<str>) -> Self {
+        Self {
+            name: bankinfo.name.chars().take(MAX_NAME).collect(),
+            iban: bankinfo.iban.electronic_str().to_owned(),
+            bic: bankinfo.bic(),
+            amount,
+            reference: reference.as_ref().chars().take(MAX_REFERENCE).collect(),
+        }
+    }
+
+    /// The payload in version 002 with UTF-8 character set.
+    pub fn payload(&self) -> Result<String> {
+        if !(0.01..=999_999_999.99).contains(&self.amount.0) {
+            bail!(
+                "Amount {} € can not be transferred with a GiroCode",
+                self.amount
+            );
+        }
+        let payload = [
+            "BCD",
+            "002",
+            "1",
+            "SCT",
+            self.bic.as_deref().unwrap_or_default(),
+            &self.name,
+            &self.iban,
+            &format!("EUR{}", self.amount),
+            // purpose and structured creditor reference
+            "",
+            "",
+            &self.reference,
+        ]
+        .join("\n");
+        if payload.len() > MAX_PAYLOAD {
+            bail!(
+                "GiroCode payload of {} bytes exceeds the maximum of {} bytes",
+                payload.len(),
+                MAX_PAYLOAD
+            );
+        }
+        Ok(payload)
+    }
+
+    /// Render the code with a caption right of the bank details.
+    pub fn render(&self, layer: &PdfLayerReference, font: &IndirectFontRef) -> Result<()> {
+        let code = QrCode::with_error_correction_level(self.payload()?, EcLevel::M)
+            .map_err(|e| eyre!("Failed to create GiroCode: {:?}", e))?;
+        let modules = code.width();
+        let colors = code.to_colors();
+        let module = Pt(SIZE / modules as f64);
+
+        let right = Pt::from(DIN_A4.width) * 0.92;
+        let top = Pt::from(DIN_A4.height) * 0.25 + Pt(20.);
+        let left = right - Pt(SIZE);
+
+        text(
+            layer,
+            Point {
+                x: left + Pt(SIZE / 2.),
+                y: top + Pt(4.),
+            },
+            "Scan to pay (GiroCode)",
+            font,
+            CAPTION_SIZE,
+            Alignment::Center,
+        )?;
+
+        layer.save_graphics_state();
+        layer.set_fill_color(Color::Greyscale(Greyscale {
+            percent: 0.,
+            icc_profile: None,
+        }));
+        for (y, row) in colors.chunks(modules).enumerate() {
+            // one rectangle per run of dark modules
+            let mut x = 0;
+            while x < modules {
+                if row[x] != qrcode::Color::Dark {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < modules && row[x] == qrcode::Color::Dark {
+                    x += 1;
+                }
+                let lower = top - module * (y + 1) as f64;
+                let upper = top - module * y as f64;
+                let x0 = left + module * start as f64;
+                let x1 = left + module * x as f64;
+                layer.add_shape(Line {
+                    points: vec![
+                        (Point { x: x0, y: lower }, false),
+                        (Point { x: x1, y: lower }, false),
+                        (Point { x: x1, y: upper }, false),
+                        (Point { x: x0, y: upper }, false),
+                    ],
+                    is_closed: true,
+                    has_fill: true,
+                    has_stroke: false,
+                    is_clipping_path: false,
+                });
+            }
+        }
+        layer.restore_graphics_state();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn girocode(amount: f64) -> GiroCode {
+        GiroCode {
+            name: "Roger Ronjason".to_owned(),
+            iban: "DE89370400440532013000".to_owned(),
+            bic: None,
+            amount: Euro(amount),
+            reference: "Reimbursement 2026-10".to_owned(),
+        }
+    }
+
+    #[test]
+    fn payload() {
+        assert_eq!(
+            girocode(118.9).payload().unwrap(),
+            "BCD\n002\n1\nSCT\n\nRoger Ronjason\nDE89370400440532013000\nEUR118.90\n\n\nReimbursement 2026-10"
+        );
+        assert!(girocode(0.).payload().is_err());
+    }
+}
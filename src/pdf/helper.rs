@@ -1,5 +1,8 @@
 use super::super::types::*;
-use super::constants::{DIN_A4, TTF_BOLD, TTF_REGULAR};
+use super::constants::{Dimensions, DIN_A4, TTF_BOLD, TTF_REGULAR};
+use super::girocode::GiroCode;
+use super::images::{downsample, encode_image, mark_encoded, ImageOptions};
+use super::signoff::SignOff;
 use super::tabular::*;
 use super::types::*;
 use crate::errors::Result;
@@ -8,7 +11,7 @@ use printpdf::image::GenericImageView;
 use printpdf::*;
 
 /// Workaround for `printpdf::Document` being incomplete unless written to a buffer or disk.
-fn flush_pdf_ops(doc: PdfDocumentReference) -> Result<lopdf::Document> {
+pub(crate) fn flush_pdf_ops(doc: PdfDocumentReference) -> Result<lopdf::Document> {
     let mut buffer = Vec::with_capacity(4 << 20);
     {
         let mut buffw = std::io::BufWriter::new(&mut buffer);
@@ -18,34 +21,37 @@ fn flush_pdf_ops(doc: PdfDocumentReference) -> Result<lopdf::Document> {
     Ok(lopdf::Document::load_mem(&buffer)?)
 }
 
-/// Compress the image a jpeg and include it appropriately.
-fn create_jpg_image(
+/// Encode the image according to `options` and include it appropriately.
+fn create_image(
     active_layer: PdfLayerReference,
     anchor: Point,
     image: image::DynamicImage,
     scale: f64,
     dpi: f64,
+    options: &ImageOptions,
 ) -> Result<()> {
-    // whatever it was, store it as jpeg into the buffer
-    let mut buffer = Vec::<u8>::with_capacity(4e6 as usize);
-    image.write_to(&mut buffer, image::ImageOutputFormat::Jpeg(80))?;
-
-    let xobj = {
-        let dim = image.dimensions();
-        let color_type = image.color();
-        let color_bits = ColorBits::from(color_type);
-        let color_space = ColorSpace::from(color_type);
-
-        printpdf::ImageXObject {
-            width: Px(dim.0 as usize),
-            height: Px(dim.1 as usize),
-            color_space,
-            bits_per_component: color_bits,
-            image_data: buffer.clone(),
-            interpolate: true,
-            image_filter: Some(ImageFilter::DCT),
-            clipping_bbox: None,
-        }
+    let encoded = encode_image(&image, options)?;
+    let xobj = printpdf::ImageXObject {
+        width: Px(encoded.width as usize),
+        height: Px(encoded.height as usize),
+        color_space: if encoded.gray {
+            ColorSpace::Greyscale
+        } else {
+            ColorSpace::Rgb
+        },
+        bits_per_component: if encoded.bilevel {
+            ColorBits::Bit1
+        } else {
+            ColorBits::Bit8
+        },
+        image_data: encoded.data,
+        interpolate: !encoded.bilevel,
+        image_filter: if encoded.bilevel {
+            None
+        } else {
+            Some(ImageFilter::DCT)
+        },
+        clipping_bbox: None,
     };
 
     let image: printpdf::Image = Image::from(xobj);
@@ -63,33 +69,48 @@ fn create_jpg_image(
 }
 
 /// Introduce a sized image page.
-pub fn sized_image_page(image: image::DynamicImage) -> Result<lopdf::Document> {
-    let (width, height) = image.dimensions();
+pub fn sized_image_page(
+    image: image::DynamicImage,
+    options: &ImageOptions,
+) -> Result<lopdf::Document> {
+    let (pixels_wide, pixels_high) = image.dimensions();
     let dpi = 200. as f64;
 
-    let height: Mm = Px(height as usize).into_pt(dpi).into();
-    let width: Mm = Px(width as usize).into_pt(dpi).into();
-
-    let scale: f64 = DIN_A4.width / width;
-
-    let allowed: std::ops::Range<f64> = 0.25..4.;
-    if !allowed.contains(&scale) {
-        log::warn!(
-            "Clamped scale factor of {:.02} to {:.02}..{:.02}",
+    let height: Mm = Px(pixels_high as usize).into_pt(dpi).into();
+    let width: Mm = Px(pixels_wide as usize).into_pt(dpi).into();
+
+    let (page, scale, anchor) = if let Some(fit) = options.fit {
+        fit.place(width, height)
+    } else {
+        let scale: f64 = DIN_A4.width / width;
+
+        let allowed: std::ops::Range<f64> = 0.25..4.;
+        if !allowed.contains(&scale) {
+            log::warn!(
+                "Clamped scale factor of {:.02} to {:.02}..{:.02}",
+                scale,
+                allowed.start,
+                allowed.end
+            );
+        }
+        let scale = scale.max(allowed.start).min(allowed.end);
+        let page = Dimensions {
+            width: width * scale,
+            height: height * scale,
+        };
+        (
+            page,
             scale,
-            allowed.start,
-            allowed.end
-        );
-    }
-    let scale = scale.max(allowed.start).min(allowed.end);
+            Point {
+                x: Pt(0.),
+                y: Pt(0.),
+            },
+        )
+    };
 
     let dim = Dimensions { height, width };
-    let (document, page1, layer1) = PdfDocument::new(
-        "Separation",
-        dim.width * scale,
-        dim.height * scale,
-        "Layer 1",
-    );
+    let (document, page1, layer1) =
+        PdfDocument::new("Separation", page.width, page.height, "Layer 1");
     let document = document.with_conformance(PdfConformance::Custom(CustomPdfConformance {
         requires_icc_profile: false,
         requires_xmp_metadata: false,
@@ -97,18 +118,24 @@ pub fn sized_image_page(image: image::DynamicImage) -> Result<lopdf::Document> {
     }));
     let active_layer = document.get_page(page1).get_layer(layer1);
 
-    create_jpg_image(
-        active_layer,
-        Point {
-            x: Pt(0.),
-            y: Pt(0.),
-        },
-        image,
-        scale,
-        dpi,
-    )?;
+    // cap the resolution on the page, adjusting the dpi keeps the size on the page
+    let longest: Pt = Mm(dim.width.0.max(dim.height.0) * scale).into();
+    let limit = (longest.0 / 72. * options.max_dpi).round() as u32;
+    let image = downsample(image, limit);
+    let dpi = dpi * image.width() as f64 / pixels_wide as f64;
+    log::debug!(
+        "Image of {}x{} pixels is embedded with {}x{} pixels",
+        pixels_wide,
+        pixels_high,
+        image.width(),
+        image.height()
+    );
+
+    create_image(active_layer, anchor, image, scale, dpi, options)?;
 
-    flush_pdf_ops(document)
+    let mut document = flush_pdf_ops(document)?;
+    mark_encoded(&mut document, options);
+    Ok(document)
 }
 
 /// Add an image with an anchor point to a given layer.
@@ -131,33 +158,66 @@ pub fn add_image(
         Alignment::Center => anchor.x - width * scale / 2.0f64,
     };
     let anchor = Point { x, y: anchor.y };
-    create_jpg_image(active_layer, anchor, image, scale, dpi)?;
+    create_image(
+        active_layer,
+        anchor,
+        image,
+        scale,
+        dpi,
+        &ImageOptions::default(),
+    )?;
 
     Ok(())
 }
 
-pub fn separation_page(desc: &str) -> Result<lopdf::Document> {
-    let dim = (Mm(20 as f64), DIN_A4.width);
-    let (doc, page1, layer1) = PdfDocument::new("Separation", dim.1, dim.0, "Layer 1");
+/// Area on the first summary page between the bank details and the GiroCode,
+/// where a digital signature is placed if the sign-off block has no employee
+/// signature field.
+pub fn signature_area() -> Rect {
+    Rect::from_corners(
+        Point {
+            x: Pt::from(DIN_A4.width) * 0.50,
+            y: Pt::from(DIN_A4.height) * 0.25 - Pt(80.),
+        },
+        Point {
+            x: Pt::from(DIN_A4.width) * 0.74,
+            y: Pt::from(DIN_A4.height) * 0.25 + Pt(20.),
+        },
+    )
+}
+
+/// Create a separation page which precedes the receipts of `row`.
+///
+/// Besides the row number, the page repeats the row of the summary table
+/// and lists the number of attached receipt pages.
+pub fn separation_page(
+    row: &Row,
+    receipt_pages: usize,
+    dimensions: Dimensions,
+) -> Result<lopdf::Document> {
+    let (doc, page1, layer1) =
+        PdfDocument::new("Separation", dimensions.width, dimensions.height, "Layer 1");
     let active_layer = doc.get_page(page1).get_layer(layer1);
 
     let font = doc.add_external_font(TTF_REGULAR)?;
+    let bold = doc.add_external_font(TTF_BOLD)?;
+
+    // font size
+    let size = 11;
 
-    let white = Color::Rgb(Rgb {
-        r: 1.,
-        g: 1.,
-        b: 1.,
-        icc_profile: None,
-    });
     let gray = Color::Greyscale(Greyscale {
         percent: 0.70,
         icc_profile: None,
     });
 
+    let width = Pt::from(dimensions.width);
+    let height = Pt::from(dimensions.height);
+    let band = Pt::from(Mm(40.));
+
     let left = Pt(0.);
-    let bottom = Pt(0.);
-    let top = dim.0.into();
-    let right = dim.1.into();
+    let right = width;
+    let top = height;
+    let bottom = height - band;
     let line = Line {
         points: vec![
             (Point { x: left, y: top }, false),
@@ -177,37 +237,68 @@ pub fn separation_page(desc: &str) -> Result<lopdf::Document> {
         is_clipping_path: false,
     };
 
-    let background = gray.clone();
-    let foreground = white.clone();
-
     active_layer.save_graphics_state();
-    active_layer.save_graphics_state();
-    active_layer.set_fill_color(background);
-
+    active_layer.set_fill_color(gray);
     active_layer.add_shape(line);
-
     active_layer.restore_graphics_state();
 
     let anchor = Point {
-        x: Pt::from(dim.1) * 0.50,
-        y: Pt::from(dim.0) * 0.20,
+        x: width * 0.10,
+        y: height - band * 0.60,
     };
+    text(
+        &active_layer,
+        anchor,
+        &format!("#{:02}", row.number),
+        &bold,
+        28,
+        Alignment::Left,
+    )?;
 
-    text(&active_layer, anchor, desc, &font, 20, Alignment::Center)?;
+    let mut entries = vec![
+        ("Description:".to_owned(), row.description.clone()),
+        ("Date:".to_owned(), row.date.format("%Y-%m-%d").to_string()),
+        ("Company:".to_owned(), row.company.clone()),
+        ("Netto:".to_owned(), format!("{} €", row.netto.as_euro())),
+    ];
+    for (percentage, tax) in row.tax_total.iter() {
+        entries.push((format!("Tax {} %:", percentage), format!("{} €", tax)));
+    }
+    entries.push(("Brutto:".to_owned(), format!("{} €", row.brutto.as_euro())));
+    entries.push((
+        "Receipts:".to_owned(),
+        match receipt_pages {
+            1 => "1 page".to_owned(),
+            n => format!("{} pages", n),
+        },
+    ));
 
-    active_layer.set_fill_color(foreground);
-    active_layer.restore_graphics_state();
+    let mut anchor = Point {
+        x: width * 0.35,
+        y: height - band - Pt::from(Mm(20.)),
+    };
+    for (label, value) in entries {
+        text(&active_layer, anchor, &label, &font, size, Alignment::Right)?;
+        anchor.x += Pt(10.0);
+        text(&active_layer, anchor, &value, &bold, size, Alignment::Left)?;
+        anchor.x -= Pt(10.0);
+        anchor.y -= Pt(22.0);
+    }
 
     flush_pdf_ops(doc)
 }
 
+/// Create the summary page, returns the document and the area
+/// covered by each row of the expenses table.
 pub fn tabular(
     bankinfo: BankInfo,
     company: CompanyInfo,
     rows: &[Row],
     totals: Totals,
     learning_budget: bool,
-) -> Result<lopdf::Document> {
+    sign_off: Option<&SignOff>,
+    girocode: Option<&GiroCode>,
+) -> Result<(lopdf::Document, Vec<Rect>)> {
     let (doc, page1, layer1) =
         PdfDocument::new("Reimbursement", DIN_A4.width, DIN_A4.height, "Layer 1");
     let active_layer = doc.get_page(page1).get_layer(layer1);
@@ -270,6 +361,7 @@ pub fn tabular(
     };
 
     let mut headers = vec![
+        "#",
         "Date",
         "Company",
         "Description",
@@ -281,9 +373,10 @@ pub fn tabular(
     .map(|x| x.to_owned())
     .collect::<Vec<_>>();
 
+    let number_column_width = 0.04;
     let date_column_width = 0.08;
     let company_column_width = 0.18;
-    let description_column_width = 0.28;
+    let description_column_width = 0.24;
     let netto_column_width = 0.09;
 
     let tax_column_width = 0.065;
@@ -297,6 +390,7 @@ pub fn tabular(
     }
 
     let mut columns = ColumnWidthSet(vec![
+        convert(number_column_width),
         convert(date_column_width),
         convert(company_column_width),
         convert(description_column_width),
@@ -312,8 +406,8 @@ pub fn tabular(
         .sorted_by(|p1, p2| p1.cmp(&p2))
         .rev()
     {
-        columns.0.insert(4, convert(tax_column_width));
-        headers.insert(4, format!("{} %", percentage));
+        columns.0.insert(5, convert(tax_column_width));
+        headers.insert(5, format!("{} %", percentage));
     }
 
     columns.0.push(convert(brutto_column_width));
@@ -345,7 +439,7 @@ pub fn tabular(
         )?;
     }
 
-    {
+    let row_areas = {
         let x = {
             let a = Pt::from(DIN_A4.width);
             if a > total_width {
@@ -366,8 +460,8 @@ pub fn tabular(
             rows,
             Some(totals.into_iter()),
         );
-        expenses.render(&styleset, columns)?;
-    }
+        expenses.render(&styleset, columns)?
+    };
 
     {
         const HEADER: &'static [&'static str] =
@@ -408,6 +502,14 @@ pub fn tabular(
         }
     }
 
+    if let Some(sign_off) = sign_off {
+        sign_off.render(&active_layer, &style1.font, &style2.font, darkgray.clone())?;
+    }
+
+    if let Some(girocode) = girocode {
+        girocode.render(&active_layer, &style1.font)?;
+    }
+
     {
         let mut anchor = Point {
             x: Pt::from(DIN_A4.width) * 0.10,
@@ -556,5 +658,5 @@ pub fn tabular(
         active_layer.restore_graphics_state();
     }
 
-    flush_pdf_ops(doc)
+    Ok((flush_pdf_ops(doc)?, row_areas))
 }
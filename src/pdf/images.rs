@@ -0,0 +1,499 @@
+//! Downsampling and recompression of the images of a document, which
+//! dominate the size of reports with photographed receipts.
+
+use super::constants::Dimensions;
+use super::page::{inherited_attribute, PageGeometry};
+use super::types::*;
+use crate::errors::*;
+
+use lopdf::{Document, Object, ObjectId, Stream};
+use printpdf::image::{
+    self, imageops::FilterType, DynamicImage, GenericImageView, GrayImage, RgbImage,
+};
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+
+/// Quality of newly encoded jpeg images, unless configured.
+pub const DEFAULT_JPEG_QUALITY: u8 = 80;
+
+/// Key of images encoded with the jpeg quality of its value already, which
+/// spares them a second lossy encoding with the same quality.
+const ENCODED_QUALITY: &[u8] = b"ShinypennyQuality";
+
+/// Color reduction applied to all images.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageMode {
+    #[default]
+    Color,
+    Grayscale,
+    /// Black and white only, the most compact for printed receipts.
+    Bilevel,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImageOptions {
+    /// Resolution cap relative to the page the image is placed on.
+    pub max_dpi: f64,
+    /// Re-encode existing jpeg images with this quality, otherwise they are
+    /// only re-encoded when downsampled or converted.
+    pub quality: Option<u8>,
+    pub mode: ImageMode,
+    /// Straighten photographed receipts, applies to image receipts only.
+    pub deskew: bool,
+    /// Crop photographed receipts to the paper, applies to image receipts only.
+    pub auto_crop: bool,
+    /// Place image receipts on pages of a fixed size, instead of pages
+    /// sized to the image.
+    pub fit: Option<PageFit>,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            max_dpi: 200.,
+            quality: None,
+            mode: ImageMode::Color,
+            deskew: false,
+            auto_crop: false,
+            fit: None,
+        }
+    }
+}
+
+impl ImageOptions {
+    /// Options compressing harder with each `step`, to fit a size limit.
+    pub fn tightened(self, step: u32) -> Self {
+        if step == 0 {
+            return self;
+        }
+        let quality = self.quality.unwrap_or(DEFAULT_JPEG_QUALITY) as i64 - 10 * step as i64;
+        Self {
+            max_dpi: (self.max_dpi * 0.8f64.powi(step as i32)).max(72.),
+            quality: Some(quality.max(25) as u8),
+            ..self
+        }
+    }
+}
+
+/// Placement of images on pages of a fixed size.
+#[derive(Debug, Clone, Copy)]
+pub struct PageFit {
+    /// The page in portrait orientation.
+    pub dimensions: Dimensions,
+    pub margin: Mm,
+}
+
+impl PageFit {
+    /// The page, turned to landscape for wide images, the scale fitting an
+    /// image of `width` by `height` within the margins and the lower left
+    /// corner of the centered image.
+    pub fn place(&self, width: Mm, height: Mm) -> (Dimensions, f64, Point) {
+        let mut page = self.dimensions;
+        if (width.0 > height.0) != (page.width.0 > page.height.0) {
+            page = Dimensions {
+                width: page.height,
+                height: page.width,
+            };
+        }
+        let available = |length: Mm| (length.0 - 2. * self.margin.0).max(1.);
+        let scale = (available(page.width) / width.0).min(available(page.height) / height.0);
+        let anchor = Point {
+            x: Mm((page.width.0 - width.0 * scale) / 2.).into(),
+            y: Mm((page.height.0 - height.0 * scale) / 2.).into(),
+        };
+        (page, scale, anchor)
+    }
+}
+
+/// Downsample images exceeding `max_dpi` and re-encode them according to
+/// `options`. Images with transparency or unusual color spaces are kept.
+pub fn optimize_images(document: &mut Document, options: &ImageOptions) -> Result<()> {
+    // longest side in pixels allowed for each image, by the largest page it is placed on
+    let mut limits = BTreeMap::<ObjectId, u32>::new();
+    for (_, page) in document.get_pages() {
+        let geometry = PageGeometry::of(document, page)?;
+        let longest = geometry.width().0.max(geometry.height().0);
+        let limit = (longest / 72. * options.max_dpi).round() as u32;
+        let mut images = Vec::new();
+        if let Some(resources) = inherited_attribute(document, page, b"Resources") {
+            collect_images(document, resources, &mut images, 0);
+        }
+        for image in images {
+            let entry = limits.entry(image).or_insert(limit);
+            *entry = (*entry).max(limit);
+        }
+    }
+
+    for (id, limit) in limits {
+        let stream = match document.get_object_mut(id) {
+            Ok(Object::Stream(stream)) => stream,
+            _ => continue,
+        };
+        match optimize_image(stream, limit, options) {
+            Ok(Some(optimized)) => *stream = optimized,
+            Ok(None) => {}
+            Err(e) => log::warn!("Keeping image {:?} as is: {}", id, e),
+        }
+        if let Ok(Object::Stream(stream)) = document.get_object_mut(id) {
+            stream.dict.remove(ENCODED_QUALITY);
+        }
+    }
+    Ok(())
+}
+
+/// Mark the jpeg images of `document` as encoded with the quality of `options`.
+pub(crate) fn mark_encoded(document: &mut Document, options: &ImageOptions) {
+    let quality = options.quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+    for object in document.objects.values_mut() {
+        if let Object::Stream(stream) = object {
+            let dict = &mut stream.dict;
+            let image = dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image");
+            let jpeg = match dict.get(b"Filter") {
+                Ok(Object::Name(name)) => name == b"DCTDecode",
+                Ok(Object::Array(filters)) => {
+                    filters.len() == 1 && filters[0].as_name().ok() == Some(b"DCTDecode")
+                }
+                _ => false,
+            };
+            if image && jpeg {
+                dict.set(ENCODED_QUALITY, quality as i64);
+            }
+        }
+    }
+}
+
+/// Collect the images of a resource dictionary, including those of nested forms.
+fn collect_images(
+    document: &Document,
+    resources: &Object,
+    images: &mut Vec<ObjectId>,
+    depth: usize,
+) {
+    // bounded, malformed documents might contain cycles
+    if depth > 8 {
+        return;
+    }
+    let xobjects = document
+        .dereference(resources)
+        .and_then(|(_, resources)| resources.as_dict())
+        .and_then(|resources| resources.get(b"XObject"))
+        .and_then(|xobjects| document.dereference(xobjects))
+        .and_then(|(_, xobjects)| xobjects.as_dict());
+    let xobjects = match xobjects {
+        Ok(xobjects) => xobjects,
+        Err(_) => return,
+    };
+    for (_, xobject) in xobjects.iter() {
+        let id = match xobject.as_reference() {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+        let stream = match document.get_object(id).and_then(Object::as_stream) {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+            Ok(b"Image") if !images.contains(&id) => images.push(id),
+            Ok(b"Form") => {
+                if let Ok(resources) = stream.dict.get(b"Resources") {
+                    collect_images(document, resources, images, depth + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The re-encoded image, or `None` if it is better left alone.
+fn optimize_image(stream: &Stream, limit: u32, options: &ImageOptions) -> Result<Option<Stream>> {
+    let dict = &stream.dict;
+    for key in [&b"SMask"[..], b"Mask", b"ImageMask", b"Decode"] {
+        if dict.has(key) {
+            return Ok(None);
+        }
+    }
+    if dict.get(b"BitsPerComponent").and_then(Object::as_i64).ok() != Some(8) {
+        return Ok(None);
+    }
+    let gray = match dict.get(b"ColorSpace").and_then(Object::as_name) {
+        Ok(b"DeviceRGB") => false,
+        Ok(b"DeviceGray") => true,
+        _ => return Ok(None),
+    };
+    let width = dict.get(b"Width").and_then(Object::as_i64)? as u32;
+    let height = dict.get(b"Height").and_then(Object::as_i64)? as u32;
+
+    let filters = match dict.get(b"Filter") {
+        Ok(Object::Name(name)) => vec![name.as_slice()],
+        Ok(Object::Array(filters)) => filters
+            .iter()
+            .map(Object::as_name)
+            .collect::<std::result::Result<Vec<_>, _>>()?,
+        _ => Vec::new(),
+    };
+    let jpeg = match filters.as_slice() {
+        [b"DCTDecode"] => true,
+        [] | [b"FlateDecode"] => false,
+        _ => return Ok(None),
+    };
+
+    let longest = width.max(height);
+    let downsample = longest > limit && limit > 0;
+    let convert = match options.mode {
+        ImageMode::Color => false,
+        ImageMode::Grayscale => !gray,
+        ImageMode::Bilevel => true,
+    };
+    let reencode = match options.quality {
+        Some(quality) => {
+            dict.get(ENCODED_QUALITY).and_then(Object::as_i64).ok() != Some(quality as i64)
+        }
+        None => false,
+    };
+    if jpeg && !downsample && !convert && !reencode {
+        return Ok(None);
+    }
+
+    let image = if jpeg {
+        image::load_from_memory_with_format(&stream.content, image::ImageFormat::Jpeg)?
+    } else {
+        let raw = if filters.is_empty() {
+            stream.content.clone()
+        } else {
+            // lopdf refuses to decompress image streams
+            let mut plain = Stream::new(dict.clone(), stream.content.clone());
+            plain.dict.remove(b"Subtype");
+            plain.decompressed_content()?
+        };
+        let image = if gray {
+            GrayImage::from_raw(width, height, raw).map(DynamicImage::ImageLuma8)
+        } else {
+            RgbImage::from_raw(width, height, raw).map(DynamicImage::ImageRgb8)
+        };
+        image.ok_or_else(|| eyre!("Image data does not match its dimensions"))?
+    };
+
+    let image = if downsample {
+        self::downsample(image, limit)
+    } else {
+        image
+    };
+
+    let encoded = encode_image(&image, options)?;
+    let mut optimized = dict.clone();
+    optimized.remove(b"DecodeParms");
+    optimized.remove(b"DecodeParams");
+    optimized.set("Width", encoded.width as i64);
+    optimized.set("Height", encoded.height as i64);
+    if encoded.gray {
+        optimized.set("ColorSpace", "DeviceGray");
+    }
+    let optimized = if encoded.bilevel {
+        optimized.set("BitsPerComponent", 1);
+        optimized.remove(b"Filter");
+        let mut stream = Stream::new(optimized, encoded.data);
+        stream.compress()?;
+        stream
+    } else {
+        optimized.set("Filter", "DCTDecode");
+        Stream::new(optimized, encoded.data)
+    };
+    // keep the original if it was smaller already
+    if jpeg && !downsample && !convert && optimized.content.len() >= stream.content.len() {
+        return Ok(None);
+    }
+    Ok(Some(optimized))
+}
+
+/// Encoded pixel data of an image.
+pub(crate) struct EncodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub gray: bool,
+    /// One bit per pixel without compression, jpeg otherwise.
+    pub bilevel: bool,
+    pub data: Vec<u8>,
+}
+
+/// Scale `image` down to at most `limit` pixels on its longest side.
+pub(crate) fn downsample(image: DynamicImage, limit: u32) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let longest = width.max(height);
+    if limit == 0 || longest <= limit {
+        return image;
+    }
+    let scale = limit as f64 / longest as f64;
+    image.resize_exact(
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+        FilterType::Triangle,
+    )
+}
+
+/// Encode `image` with the color reduction and quality of `options`,
+/// transparency is dropped.
+pub(crate) fn encode_image(image: &DynamicImage, options: &ImageOptions) -> Result<EncodedImage> {
+    let (width, height) = image.dimensions();
+    let gray = matches!(image, DynamicImage::ImageLuma8(_));
+    let encoded = match options.mode {
+        ImageMode::Bilevel => EncodedImage {
+            width,
+            height,
+            gray: true,
+            bilevel: true,
+            data: bilevel(&image.to_luma8()),
+        },
+        mode => {
+            let gray = gray || mode == ImageMode::Grayscale;
+            let image = if gray {
+                DynamicImage::ImageLuma8(image.to_luma8())
+            } else {
+                DynamicImage::ImageRgb8(image.to_rgb8())
+            };
+            let quality = options.quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+            let mut data = Vec::new();
+            image.write_to(&mut data, image::ImageOutputFormat::Jpeg(quality))?;
+            EncodedImage {
+                width,
+                height,
+                gray,
+                bilevel: false,
+                data,
+            }
+        }
+    };
+    Ok(encoded)
+}
+
+/// Pack the image into one bit per pixel with rows padded to full bytes,
+/// thresholded at the level separating paper and ink best.
+fn bilevel(image: &GrayImage) -> Vec<u8> {
+    let threshold = otsu_threshold(image);
+    let (width, height) = image.dimensions();
+    let stride = (width as usize).div_ceil(8);
+    let mut packed = vec![0u8; stride * height as usize];
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel.0[0] > threshold {
+            packed[y as usize * stride + x as usize / 8] |= 0x80 >> (x % 8);
+        }
+    }
+    packed
+}
+
+/// Threshold maximizing the variance between the two classes of pixels.
+pub(crate) fn otsu_threshold(image: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in image.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+    let total = image.pixels().len() as f64;
+    let sum = histogram
+        .iter()
+        .enumerate()
+        .map(|(level, count)| level as f64 * *count as f64)
+        .sum::<f64>();
+
+    let (mut background, mut background_sum) = (0f64, 0f64);
+    let (mut best, mut best_variance) = (127u8, 0f64);
+    for (level, count) in histogram.iter().enumerate() {
+        background += *count as f64;
+        if background == 0. {
+            continue;
+        }
+        let foreground = total - background;
+        if foreground == 0. {
+            break;
+        }
+        background_sum += level as f64 * *count as f64;
+        let background_mean = background_sum / background;
+        let foreground_mean = (sum - background_sum) / foreground;
+        let variance = background * foreground * (background_mean - foreground_mean).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best = level as u8;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bilevel_packing() {
+        let image = GrayImage::from_fn(10, 2, |x, y| {
+            image::Luma([if (x + y) % 2 == 0 { 250 } else { 10 }])
+        });
+        assert_eq!(
+            bilevel(&image),
+            vec![0b1010_1010, 0b1000_0000, 0b0101_0101, 0b0100_0000]
+        );
+    }
+
+    #[test]
+    fn fit_to_page() {
+        let fit = PageFit {
+            dimensions: super::super::constants::DIN_A4,
+            margin: Mm(10.),
+        };
+        // wide images are placed on landscape pages
+        let (page, scale, anchor) = fit.place(Mm(554.), Mm(190.));
+        assert_eq!((page.width.0, page.height.0), (297., 210.));
+        assert!((scale - 0.5).abs() < 1e-9);
+        assert!((anchor.x - Pt::from(Mm(10.))).0.abs() < 1e-6);
+        assert!((anchor.y - Pt::from(Mm(57.5))).0.abs() < 1e-6);
+
+        let (page, scale, anchor) = fit.place(Mm(50.), Mm(100.));
+        assert_eq!((page.width.0, page.height.0), (210., 297.));
+        assert!((scale - 2.77).abs() < 1e-9);
+        assert!((anchor.y - Pt::from(Mm(10.))).0.abs() < 1e-6);
+    }
+
+    #[test]
+    fn tightened() {
+        let options = ImageOptions::default();
+        assert_eq!(options.tightened(0).quality, None);
+        let tighter = options.tightened(2);
+        assert_eq!(tighter.quality, Some(60));
+        assert!((tighter.max_dpi - 128.).abs() < 1e-9);
+        assert_eq!(options.tightened(20).max_dpi, 72.);
+    }
+
+    #[test]
+    fn encoded_once() {
+        let waves = RgbImage::from_fn(300, 200, |x, y| {
+            let level = (127. + 100. * (x as f64 / 9. + y as f64 / 14.).sin()) as u8;
+            image::Rgb([level, level / 2, 255 - level])
+        });
+        let options = ImageOptions {
+            quality: Some(60),
+            ..ImageOptions::default()
+        };
+        let document =
+            super::super::helper::sized_image_page(DynamicImage::ImageRgb8(waves), &options)
+                .unwrap();
+        let content = |document: &Document| {
+            document
+                .objects
+                .values()
+                .find_map(|object| match object {
+                    Object::Stream(stream) if stream.dict.has(b"Width") => Some(stream.clone()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        let mut optimized = document.clone();
+        optimize_images(&mut optimized, &options).unwrap();
+        let image = content(&optimized);
+        assert_eq!(image.content, content(&document).content);
+        assert!(!image.dict.has(ENCODED_QUALITY));
+
+        let mut tightened = document.clone();
+        optimize_images(&mut tightened, &options.tightened(2)).unwrap();
+        assert!(content(&tightened).content.len() < image.content.len());
+    }
+}
@@ -0,0 +1,87 @@
+//! Document information dictionary of the combined document.
+
+use super::strings::encode_text_string;
+use crate::errors::*;
+
+use lopdf::{Dictionary, Document, Object};
+
+use std::collections::BTreeMap;
+
+/// Keys of the information dictionary which are set from dedicated fields.
+const STANDARD_KEYS: &[&str] = &[
+    "Title",
+    "Author",
+    "Subject",
+    "Keywords",
+    "Creator",
+    "Producer",
+    "CreationDate",
+    "ModDate",
+    "Trapped",
+];
+
+/// Metadata describing the document as a whole.
+#[derive(Debug, Clone, Default)]
+pub struct ReportInfo {
+    pub title: String,
+    pub author: String,
+    pub subject: String,
+    pub keywords: Vec<String>,
+    /// Additional, non-standard entries.
+    pub custom: BTreeMap<String, String>,
+}
+
+/// Name and version of this tool, used as creator and producer.
+pub fn producer() -> String {
+    format!("shinypenny {}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Format a date as pdf date string, i.e. `D:20210301120000+01'00'`.
+pub fn pdf_date(date: chrono::DateTime<chrono::Local>) -> String {
+    let offset = date.format("%z").to_string();
+    format!(
+        "{}{}'{}'",
+        date.format("D:%Y%m%d%H%M%S"),
+        &offset[..3],
+        &offset[3..]
+    )
+}
+
+/// Replace the information dictionary of `document`.
+///
+/// Creator, producer and the creation date are filled in automatically.
+pub fn set_document_info(document: &mut Document, info: &ReportInfo) -> Result<()> {
+    let producer = producer();
+    let now = pdf_date(chrono::Local::now());
+
+    let mut dict = Dictionary::new();
+    dict.set("Title", encode_text_string(&info.title));
+    dict.set("Author", encode_text_string(&info.author));
+    dict.set("Subject", encode_text_string(&info.subject));
+    dict.set("Keywords", encode_text_string(&info.keywords.join(", ")));
+    dict.set("Creator", encode_text_string(&producer));
+    dict.set("Producer", encode_text_string(&producer));
+    dict.set("CreationDate", encode_text_string(&now));
+    dict.set("ModDate", encode_text_string(&now));
+    for (key, value) in info.custom.iter() {
+        if STANDARD_KEYS.contains(&key.as_str()) {
+            log::warn!(
+                "Ignoring custom metadata field {}, it is set by shinypenny",
+                key
+            );
+            continue;
+        }
+        dict.set(key.as_bytes().to_vec(), encode_text_string(value));
+    }
+
+    match document.trailer.get(b"Info").and_then(Object::as_reference) {
+        Ok(id) => {
+            document.objects.insert(id, Object::Dictionary(dict));
+        }
+        Err(_) => {
+            let id = document.add_object(dict);
+            document.trailer.set("Info", id);
+        }
+    }
+    Ok(())
+}
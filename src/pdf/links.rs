@@ -0,0 +1,58 @@
+//! Internal links between pages of the combined document.
+
+use super::overlay::Canvas;
+use super::page::add_annotation;
+use super::tabular::{text, Alignment};
+use super::types::*;
+use crate::errors::*;
+
+use lopdf::{dictionary, Document, Object, ObjectId};
+
+/// Add a link annotation covering `rect`, given in user space, to `page`
+/// which jumps to the page `target`.
+pub fn add_link(
+    document: &mut Document,
+    page: ObjectId,
+    rect: Rect,
+    target: ObjectId,
+) -> Result<()> {
+    let annotation = document.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Link",
+        "Rect" => rect.to_pdf_array(),
+        "Border" => vec![0.into(), 0.into(), 0.into()],
+        "Dest" => vec![Object::Reference(target), "Fit".into()],
+    });
+
+    add_annotation(document, page, annotation)
+}
+
+/// Draw a small link in the upper left corner which leads back to `target`.
+pub fn back_link(canvas: &mut Canvas, target: usize) -> Result<()> {
+    const LABEL: &str = "« Summary";
+    let size = 8;
+    let anchor = Point {
+        x: Pt(12.),
+        y: canvas.height - Pt(18.),
+    };
+    let width = text(
+        &canvas.layer,
+        anchor,
+        LABEL,
+        &canvas.font,
+        size,
+        Alignment::Left,
+    )?;
+    let rect = Rect::from_corners(
+        Point {
+            x: anchor.x - Pt(2.),
+            y: anchor.y - Pt(3.),
+        },
+        Point {
+            x: anchor.x + width,
+            y: anchor.y + Pt(size as f64),
+        },
+    );
+    canvas.link(rect, target);
+    Ok(())
+}
@@ -15,32 +15,194 @@ mod tabular;
 mod helper;
 pub use self::helper::*;
 
+mod strings;
+
+mod outline;
+pub use self::outline::*;
+
+mod page;
+use self::page::inherited_attribute;
+
+mod links;
+pub use self::links::*;
+
+mod overlay;
+pub use self::overlay::*;
+
+mod stamps;
+pub use self::stamps::*;
+
+mod attachments;
+pub use self::attachments::*;
+
+mod info;
+pub use self::info::*;
+
+mod pdfa;
+pub use self::pdfa::*;
+
+mod encryption;
+pub use self::encryption::*;
+
+mod signature;
+pub use self::signature::*;
+
+mod signoff;
+pub use self::signoff::*;
+
+mod girocode;
+pub use self::girocode::*;
+
+mod images;
+pub use self::images::*;
+
+mod photo;
+
+mod frames;
+
+mod textual;
+
+mod extract;
+pub use self::extract::*;
+
+mod fingerprint;
+pub use self::fingerprint::*;
+
+mod declaration;
+pub use self::declaration::*;
+use self::textual::{text_receipt, TextKind};
+
+mod nup;
+pub use self::nup::*;
+
 use std::io::{BufRead, Read, Seek, SeekFrom};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use crate::archive;
+use crate::types::ReceiptSpec;
 
 use fs_err as fs;
 
-fn load_image(stream: impl BufRead + Seek, _ext: String) -> Result<Document> {
-    let reader = image::io::Reader::new(stream);
-    let reader = image::io::Reader::with_guessed_format(reader)?;
-    let image = reader.decode()?;
+/// Load an image receipt, along with the perceptual hash of its first frame.
+fn load_image(
+    mut stream: impl BufRead + Seek,
+    ext: String,
+    options: &ImageOptions,
+) -> Result<(Document, Option<u64>)> {
+    let mut bytes = Vec::new();
+    stream.read_to_end(&mut bytes)?;
+    let frames = frames::decode(&bytes, &ext)?;
+    let perceptual = frames.first().and_then(perceptual_hash);
+
+    // phone cameras store the pixels as captured and only tag the rotation
+    let orientation = photo::orientation(&bytes);
+    let mut documents = frames
+        .into_iter()
+        .map(|mut image| {
+            if let Some(orientation) = orientation {
+                image = photo::orient(image, orientation);
+            }
+            // straighten first, the paper is cropped best with its edges upright
+            if options.deskew {
+                image = photo::deskew(image);
+            }
+            if options.auto_crop {
+                image = photo::auto_crop(image);
+            }
+            sized_image_page(image, options)
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-    sized_image_page(image)
+    let document = if documents.len() == 1 {
+        documents.remove(0)
+    } else {
+        log::info!(
+            "Image has {} frames, adding a page for each",
+            documents.len()
+        );
+        combine(&mut documents)?
+    };
+    Ok((document, perceptual))
 }
 
 use infer::Infer;
 
-fn load_pdf(path: &Path, buffered: impl BufRead) -> Result<lopdf::Document> {
-    let document = lopdf::Document::load_from(buffered)
+fn load_pdf(
+    path: &Path,
+    mut buffered: impl BufRead,
+    passwords: &dyn Fn(&Path) -> Result<Vec<String>>,
+) -> Result<lopdf::Document> {
+    let mut buffer = Vec::new();
+    buffered.read_to_end(&mut buffer)?;
+    let mut document = lopdf::Document::load_mem(&buffer)
         .map_err(|e| eyre!("Could not open receipt {}: {:?}", path.display(), e))?;
+    if is_encrypted(&document) {
+        log::info!("Decrypting receipt {}", path.display());
+        decrypt(&mut document, &buffer, &passwords(path)?)
+            .wrap_err_with(|| eyre!("Could not decrypt receipt {}", path.display()))?;
+    }
     Ok(document)
 }
 
-pub fn load_receipt(path: impl AsRef<Path>) -> Result<lopdf::Document> {
-    let path = path.as_ref();
-    let f = fs::File::open(&path)?;
+fn load_text(
+    mut buffered: impl BufRead,
+    kind: TextKind,
+    dimensions: constants::Dimensions,
+) -> Result<Document> {
+    let mut bytes = Vec::new();
+    buffered.read_to_end(&mut bytes)?;
+    text_receipt(&bytes, kind, dimensions)
+}
+
+/// A loaded receipt.
+pub struct Receipt {
+    pub document: Document,
+    /// The receipt was an image, placed on a page of its own.
+    pub image: bool,
+    pub fingerprint: Fingerprint,
+}
+
+/// Load a receipt, the `passwords` of its path, archive members as
+/// `archive.zip!/member.pdf`, are only looked up for encrypted pdfs.
+///
+/// Only the selected pages are kept and turned as given by `spec`, text
+/// receipts are rendered onto pages of `dimensions`.
+pub fn load_receipt(
+    spec: &ReceiptSpec,
+    passwords: &dyn Fn(&Path) -> Result<Vec<String>>,
+    options: &ImageOptions,
+    dimensions: constants::Dimensions,
+) -> Result<Receipt> {
+    let name = PathBuf::from(spec.to_string());
+    // the whole content is needed for its fingerprint anyways
+    let content = if let Some(ref member) = spec.member {
+        archive::read_member(&spec.path, member)?
+    } else {
+        fs::read(&spec.path)?
+    };
+    let mut receipt = load_file(&name, &content, passwords, options, dimensions)?;
+    receipt.fingerprint.select_pages(&spec.pages);
+    if !spec.pages.is_empty() {
+        page::select_pages(&mut receipt.document, &spec.pages)
+            .wrap_err_with(|| eyre!("Invalid page selection of {}", spec))?;
+    }
+    if spec.rotate != 0 {
+        page::rotate_pages(&mut receipt.document, spec.rotate)?;
+    }
+    Ok(receipt)
+}
 
-    let mut buffered = std::io::BufReader::new(f);
+/// Load the receipt `path` from its `content`, the path is used for messages
+/// and as fallback to determine the file type.
+fn load_file(
+    path: &Path,
+    content: &[u8],
+    passwords: &dyn Fn(&Path) -> Result<Vec<String>>,
+    options: &ImageOptions,
+    dimensions: constants::Dimensions,
+) -> Result<Receipt> {
+    let fingerprint = |perceptual| Fingerprint::new(content, perceptual);
+    let mut buffered = std::io::Cursor::new(content);
     let mut magic = vec![0u8; 16];
     buffered.read_exact(&mut magic).wrap_err_with(|| {
         eyre!(
@@ -59,36 +221,77 @@ pub fn load_receipt(path: impl AsRef<Path>) -> Result<lopdf::Document> {
             "Inferring by magic based mime type {}",
             detected.mime_type()
         );
-        let document = match detected.mime_type() {
-            mime if mime.starts_with("image/") => {
-                load_image(buffered, detected.extension().to_owned())?
+        let receipt = match detected.mime_type() {
+            "image/png" | "image/jpeg" | "image/webp" | "image/bmp" | "image/tiff"
+            | "image/gif" => {
+                let (document, perceptual) =
+                    load_image(buffered, detected.extension().to_owned(), options)?;
+                Receipt {
+                    document,
+                    image: true,
+                    fingerprint: fingerprint(perceptual),
+                }
             }
-            "application/pdf" => load_pdf(path, buffered)?,
+            "application/pdf" => Receipt {
+                document: load_pdf(path, buffered, passwords)?,
+                image: false,
+                fingerprint: fingerprint(None),
+            },
+            "text/html" => Receipt {
+                document: load_text(buffered, TextKind::Html, dimensions)?,
+                image: false,
+                fingerprint: fingerprint(None),
+            },
             mime => bail!("Can not hande {} mime type of {}", mime, path.display()),
         };
-        Ok(document)
+        Ok(receipt)
     } else if let Some(ext) = path.extension().map(|x| x.to_string_lossy()) {
         log::warn!("Could not infer mime type from initial 16 bytes, fallback to file extension");
-        let document = match ext.as_ref() {
-            "png" | "jpeg" | "jpg" | "webp" | "bmp" => {
-                load_image(buffered, ext.as_ref().to_owned())?
+        let receipt = match ext.as_ref() {
+            "png" | "jpeg" | "jpg" | "webp" | "bmp" | "tif" | "tiff" | "gif" => {
+                let (document, perceptual) =
+                    load_image(buffered, ext.as_ref().to_owned(), options)?;
+                Receipt {
+                    document,
+                    image: true,
+                    fingerprint: fingerprint(perceptual),
+                }
+            }
+            "pdf" => Receipt {
+                document: load_pdf(path, buffered, passwords)?,
+                image: false,
+                fingerprint: fingerprint(None),
+            },
+            "txt" | "eml" | "html" | "htm" => {
+                let kind = match ext.as_ref() {
+                    "txt" => TextKind::Plain,
+                    "eml" => TextKind::Email,
+                    _ => TextKind::Html,
+                };
+                Receipt {
+                    document: load_text(buffered, kind, dimensions)?,
+                    image: false,
+                    fingerprint: fingerprint(None),
+                }
             }
-            "pdf" => load_pdf(path, buffered)?,
             mime => bail!("Can not hande {} mime type of {}", mime, path.display()),
         };
-        Ok(document)
+        Ok(receipt)
     } else {
         bail!("Failed to determine file type of {}", path.display());
     }
 }
 
+const INHERITABLE_PAGE_ATTRIBUTES: &[&[u8]] = &[b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
 /// Combine multiple pdf files into one.
 pub fn combine(documents: &mut [Document]) -> Result<Document> {
     // Define a starting max_id (will be used as start index for object_ids)
     let mut max_id = 1;
 
-    // Collect all Documents Objects grouped by a map
-    let mut documents_pages = BTreeMap::new();
+    // Collect all Documents Objects grouped by a map, pages are kept
+    // in page order so page indices are preserved across the merge
+    let mut documents_pages = Vec::<(ObjectId, Object)>::new();
     let mut documents_objects = BTreeMap::new();
 
     for (idx, document) in documents.into_iter().enumerate() {
@@ -99,15 +302,20 @@ pub fn combine(documents: &mut [Document]) -> Result<Document> {
         max_id = document.max_id + 1;
 
         let pages = document.get_pages();
-        let pages = pages
-            .into_iter()
-            .map(|(_, object_id)| {
-                (
-                    object_id,
-                    document.get_object(object_id).unwrap().to_owned(),
-                )
-            })
-            .collect::<BTreeMap<ObjectId, Object>>();
+        let pages = pages.into_values().map(|object_id| {
+            let mut page = document.get_object(object_id).unwrap().to_owned();
+            // the page tree is replaced, so inherited attributes must move into the page
+            if let Ok(dictionary) = page.as_dict_mut() {
+                for key in INHERITABLE_PAGE_ATTRIBUTES {
+                    if !dictionary.has(key) {
+                        if let Some(value) = inherited_attribute(document, object_id, key) {
+                            dictionary.set(*key, value.clone());
+                        }
+                    }
+                }
+            }
+            (object_id, page)
+        });
 
         documents_pages.extend(pages);
         documents_objects.extend(document.objects.clone());
@@ -160,13 +368,9 @@ pub fn combine(documents: &mut [Document]) -> Result<Document> {
                 }
             }
             "Page" => {} // Ignored, processed later and separately
-            "Outlines" => {
-                // Ignored, not supported yet
-                log::warn!("Dropping outlines");
-            }
-            "Outline" => {
-                // Ignored, not supported yet
-                log::warn!("Dropping outlines");
+            "Outlines" | "Outline" => {
+                // Ignored, the outline is regenerated for the merged document
+                log::debug!("Dropping outlines");
             }
             x => {
                 log::info!("Adding other object {} {:?}", x, &object);
@@ -226,7 +430,7 @@ pub fn combine(documents: &mut [Document]) -> Result<Document> {
     if let Ok(dictionary) = catalog_object.1.as_dict() {
         let mut dictionary = dictionary.clone();
         dictionary.set("Pages", pages_object.0);
-        dictionary.remove(b"Outlines"); // Outlines not supported in merged PDFs
+        dictionary.remove(b"Outlines"); // Outlines are regenerated, see `add_outline`
 
         document
             .objects
@@ -0,0 +1,323 @@
+//! Several small receipts on a single page.
+//!
+//! Parking, bus or coffee receipts would otherwise occupy a page each.
+//! Every receipt page is imported as form xobject and drawn scaled onto
+//! a shared page, labelled with the number of its row.
+
+use super::constants::{Dimensions, TTF_REGULAR};
+use super::helper::flush_pdf_ops;
+use super::overlay::{adopt_shared_objects, draw_xobjects, renumber_after};
+use super::page::{inherited_attribute, PageGeometry};
+use super::tabular::{text, Alignment};
+use super::types::*;
+use super::Receipt;
+use crate::errors::*;
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use printpdf::PdfDocument;
+use serde::Deserialize;
+
+/// Space between neighbouring receipts.
+const GAP: Mm = Mm(5.);
+
+/// Font size of the labels.
+const LABEL_SIZE: i32 = 8;
+
+/// Space above every receipt reserved for its label.
+const LABEL_HEIGHT: Pt = Pt(12.);
+
+/// How receipts are arranged on a shared page.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NUpLayout {
+    /// Cells of equal size, `columns` by `rows`.
+    #[default]
+    Grid,
+    /// `rows` of equal height, each holding as many receipts as fit side by side.
+    Packed,
+}
+
+/// Placement of small receipts several per page.
+#[derive(Debug, Clone, Copy)]
+pub struct NUp {
+    pub layout: NUpLayout,
+    pub columns: usize,
+    pub rows: usize,
+    /// The shared pages, in portrait orientation.
+    pub dimensions: Dimensions,
+    pub margin: Mm,
+    /// Place small single page pdfs as well, not only images.
+    pub pdfs: bool,
+}
+
+/// A single page receipt to be placed on a shared page.
+pub struct Tile {
+    pub label: String,
+    pub document: Document,
+}
+
+/// Location of a receipt on a shared page, in display coordinates.
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    /// Index of the receipt.
+    pub index: usize,
+    /// Lower left corner of the scaled receipt.
+    pub anchor: Point,
+    pub scale: f64,
+    /// Start of the baseline of the label.
+    pub label: Point,
+}
+
+impl NUp {
+    /// Whether `receipt` is placed on a shared page. Images always are,
+    /// pdfs if enabled and their single page is no larger than half the
+    /// page in either direction.
+    pub fn accepts(&self, receipt: &Receipt) -> bool {
+        let pages = receipt.document.get_pages();
+        if pages.len() != 1 {
+            return false;
+        }
+        if receipt.image {
+            return true;
+        }
+        if !self.pdfs {
+            return false;
+        }
+        let geometry = pages
+            .values()
+            .next()
+            .and_then(|page| PageGeometry::of(&receipt.document, *page).ok());
+        if let Some(geometry) = geometry {
+            let (width, height) = (geometry.width().0, geometry.height().0);
+            let half_width = Pt::from(self.dimensions.width).0 / 2.;
+            let half_height = Pt::from(self.dimensions.height).0 / 2.;
+            let fits = |width: f64, height: f64| width <= half_width && height <= half_height;
+            fits(width, height) || fits(height, width)
+        } else {
+            false
+        }
+    }
+
+    /// Arrange receipts of the given display `sizes` in points, returns
+    /// the placements of each shared page.
+    pub fn arrange(&self, sizes: &[(f64, f64)]) -> Vec<Vec<Placement>> {
+        let width = Pt::from(self.dimensions.width).0;
+        let height = Pt::from(self.dimensions.height).0;
+        let margin = Pt::from(self.margin).0;
+        let gap = Pt::from(GAP).0;
+
+        let left = margin;
+        let top = height - margin;
+        let content_width = (width - 2. * margin).max(1.);
+        let content_height = (height - 2. * margin).max(1.);
+        let rows = self.rows.max(1);
+        let row_height = ((content_height - gap * (rows - 1) as f64) / rows as f64).max(1.);
+        // space for the receipt itself below the label
+        let body = (row_height - LABEL_HEIGHT.0).max(1.);
+        let place = |index: usize, x: f64, row: usize, scale: f64, height: f64| {
+            let row_top = top - row as f64 * (row_height + gap);
+            Placement {
+                index,
+                anchor: Point {
+                    x: Pt(x),
+                    y: Pt(row_top - LABEL_HEIGHT.0 - height * scale),
+                },
+                scale,
+                label: Point {
+                    x: Pt(x),
+                    y: Pt(row_top - LABEL_SIZE as f64),
+                },
+            }
+        };
+
+        let mut pages = Vec::new();
+        let mut page = Vec::new();
+        match self.layout {
+            NUpLayout::Grid => {
+                let columns = self.columns.max(1);
+                let cell_width =
+                    ((content_width - gap * (columns - 1) as f64) / columns as f64).max(1.);
+                for (index, &(width, height)) in sizes.iter().enumerate() {
+                    let cell = index % (columns * rows);
+                    if cell == 0 && !page.is_empty() {
+                        pages.push(std::mem::take(&mut page));
+                    }
+                    let (column, row) = (cell % columns, cell / columns);
+                    let scale = (cell_width / width).min(body / height);
+                    // centered horizontally within the cell
+                    let x = left
+                        + column as f64 * (cell_width + gap)
+                        + (cell_width - width * scale) / 2.;
+                    page.push(place(index, x, row, scale, height));
+                }
+            }
+            NUpLayout::Packed => {
+                let (mut x, mut row) = (0., 0);
+                for (index, &(width, height)) in sizes.iter().enumerate() {
+                    let scale = (body / height).min(content_width / width);
+                    if x > 0. && x + width * scale > content_width {
+                        x = 0.;
+                        row += 1;
+                    }
+                    if row == rows {
+                        pages.push(std::mem::take(&mut page));
+                        row = 0;
+                    }
+                    page.push(place(index, left + x, row, scale, height));
+                    x += width * scale + gap;
+                }
+            }
+        }
+        if !page.is_empty() {
+            pages.push(page);
+        }
+        pages
+    }
+
+    /// Place `tiles` onto shared pages, returns the document along
+    /// with the zero based page index of each tile.
+    pub fn compose(&self, tiles: Vec<Tile>) -> Result<(Document, Vec<usize>)> {
+        let sizes = tiles
+            .iter()
+            .map(|tile| {
+                let page = *tile
+                    .document
+                    .get_pages()
+                    .values()
+                    .next()
+                    .ok_or_else(|| eyre!("Receipt {} has no page", tile.label))?;
+                let geometry = PageGeometry::of(&tile.document, page)?;
+                Ok((geometry.width().0, geometry.height().0))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let pages = self.arrange(&sizes);
+        if pages.is_empty() {
+            bail!("No receipts to place on a shared page");
+        }
+
+        let (width, height) = (self.dimensions.width, self.dimensions.height);
+        let (doc, page1, layer1) = PdfDocument::new("Receipts", width, height, "Layer 1");
+        let font = doc.add_external_font(TTF_REGULAR)?;
+        for (n, placements) in pages.iter().enumerate() {
+            let (page, layer) = if n == 0 {
+                (page1, layer1)
+            } else {
+                doc.add_page(width, height, "Layer 1")
+            };
+            let layer = doc.get_page(page).get_layer(layer);
+            for placement in placements {
+                text(
+                    &layer,
+                    placement.label,
+                    &tiles[placement.index].label,
+                    &font,
+                    LABEL_SIZE,
+                    Alignment::Left,
+                )?;
+            }
+        }
+        let mut document = flush_pdf_ops(doc)?;
+
+        let page_ids = document
+            .get_pages()
+            .into_values()
+            .collect::<Vec<ObjectId>>();
+        let mut tile_pages = vec![0; tiles.len()];
+        // placements are in order of the tiles
+        let mut tiles = tiles.into_iter();
+        for ((n, placements), page) in pages.iter().enumerate().zip(page_ids) {
+            let mut forms = Vec::with_capacity(placements.len());
+            for placement in placements {
+                let tile = tiles
+                    .next()
+                    .expect("Every tile has exactly one placement. qed");
+                tile_pages[placement.index] = n;
+                let form = import_page(&mut document, tile.document)?;
+                let scale = placement.scale;
+                let (x, y) = (placement.anchor.x.0, placement.anchor.y.0);
+                forms.push((form, [scale, 0., 0., scale, x, y]));
+            }
+            draw_xobjects(&mut document, page, &forms)?;
+        }
+        Ok((document, tile_pages))
+    }
+}
+
+/// Import the first page of `source` as form xobject in display coordinates.
+fn import_page(document: &mut Document, mut source: Document) -> Result<ObjectId> {
+    renumber_after(document, &mut source);
+    let page = *source
+        .get_pages()
+        .values()
+        .next()
+        .ok_or_else(|| eyre!("Receipt has no page"))?;
+    let geometry = PageGeometry::of(&source, page)?;
+    let content = source.get_page_content(page)?;
+    let resources = inherited_attribute(&source, page, b"Resources")
+        .cloned()
+        .unwrap_or_else(|| Object::Dictionary(Dictionary::new()));
+    let form = Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => geometry.media_box().iter().map(|x| Object::Real(*x)).collect::<Vec<_>>(),
+            "Matrix" => geometry.inverse_matrix().iter().map(|x| Object::Real(*x)).collect::<Vec<_>>(),
+            "Resources" => resources,
+        },
+        content,
+    );
+    adopt_shared_objects(document, source);
+    Ok(document.add_object(form))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::constants::DIN_A4;
+    use super::*;
+
+    fn n_up(layout: NUpLayout) -> NUp {
+        NUp {
+            layout,
+            columns: 2,
+            rows: 2,
+            dimensions: DIN_A4,
+            margin: Mm(10.),
+            pdfs: false,
+        }
+    }
+
+    #[test]
+    fn grid() {
+        let sizes = vec![(100., 200.); 5];
+        let pages = n_up(NUpLayout::Grid).arrange(&sizes);
+        assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), vec![4, 1]);
+
+        let [first, second, third, _] = [pages[0][0], pages[0][1], pages[0][2], pages[0][3]];
+        // side by side, then below
+        assert!(first.anchor.x < second.anchor.x);
+        assert!((first.anchor.y.0 - second.anchor.y.0).abs() < 1e-9);
+        assert!(third.anchor.y < first.anchor.y);
+        // the label sits on top of the receipt
+        assert!(first.label.y.0 > first.anchor.y.0 + 200. * first.scale);
+    }
+
+    #[test]
+    fn packed() {
+        // narrow receipts share a row, until the row is full
+        let sizes = vec![
+            (100., 250.),
+            (100., 250.),
+            (100., 250.),
+            (400., 100.),
+            (100., 250.),
+        ];
+        let pages = n_up(NUpLayout::Packed).arrange(&sizes);
+        assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), vec![4, 1]);
+        assert!((pages[0][0].anchor.y.0 - pages[0][2].anchor.y.0).abs() < 1e-9);
+        assert!(pages[0][3].anchor.y < pages[0][2].anchor.y);
+
+        // the wide receipt is shrunk to the width of the page
+        let content_width = Pt::from(Mm(190.)).0;
+        assert!((400. * pages[0][3].scale - content_width).abs() < 1e-6);
+    }
+}
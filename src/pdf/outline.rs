@@ -0,0 +1,334 @@
+//! Document outline, a.k.a. bookmarks, of the combined document.
+
+use super::strings::{decode_text_string, encode_text_string};
+use crate::errors::*;
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId};
+
+use std::collections::HashSet;
+
+/// A single outline entry pointing to a page of a document.
+#[derive(Debug, Clone)]
+pub struct OutlineEntry {
+    pub title: String,
+    /// Zero based page index within the document.
+    pub page: usize,
+    pub children: Vec<OutlineEntry>,
+}
+
+impl OutlineEntry {
+    pub fn new(title: impl Into<String>, page: usize) -> Self {
+        Self {
+            title: title.into(),
+            page,
+            children: Vec::new(),
+        }
+    }
+
+    /// Shift the entry and all its children by `offset` pages.
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.page += offset;
+        self.children = self
+            .children
+            .into_iter()
+            .map(|child| child.offset(offset))
+            .collect();
+        self
+    }
+}
+
+/// Extract the existing outline of a document.
+///
+/// Destinations are resolved to page indices, entries whose destination
+/// can not be resolved point to the first page.
+pub fn extract_outline(document: &Document) -> Vec<OutlineEntry> {
+    let pages = document
+        .get_pages()
+        .into_values()
+        .collect::<Vec<ObjectId>>();
+    let first = document
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Outlines"))
+        .and_then(|outlines| document.dereference(outlines))
+        .and_then(|(_, outlines)| outlines.as_dict())
+        .and_then(|outlines| outlines.get(b"First"))
+        .and_then(Object::as_reference);
+
+    let mut visited = HashSet::new();
+    match first {
+        Ok(first) => extract_siblings(document, first, &pages, &mut visited),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn extract_siblings(
+    document: &Document,
+    first: ObjectId,
+    pages: &[ObjectId],
+    visited: &mut HashSet<ObjectId>,
+) -> Vec<OutlineEntry> {
+    let mut entries = Vec::new();
+    let mut cursor = Some(first);
+    while let Some(id) = cursor.take() {
+        // malformed documents might contain cycles
+        if !visited.insert(id) {
+            log::warn!("Outline item {:?} is referenced more than once", id);
+            break;
+        }
+        let item = if let Ok(item) = document.get_dictionary(id) {
+            item
+        } else {
+            break;
+        };
+
+        let title = item
+            .get(b"Title")
+            .and_then(|title| document.dereference(title))
+            .and_then(|(_, title)| title.as_str())
+            .map(decode_text_string)
+            .unwrap_or_default();
+
+        let page = destination_page(document, item)
+            .and_then(|page_id| pages.iter().position(|id| *id == page_id))
+            .unwrap_or_else(|| {
+                log::debug!("Outline item >{}< has no resolvable destination", title);
+                0
+            });
+
+        let mut entry = OutlineEntry::new(title, page);
+        if let Ok(child) = item.get(b"First").and_then(Object::as_reference) {
+            entry.children = extract_siblings(document, child, pages, visited);
+        }
+        entries.push(entry);
+
+        cursor = item.get(b"Next").and_then(Object::as_reference).ok();
+    }
+    entries
+}
+
+/// Find the target page of an outline item, either given as `Dest`
+/// or as the destination of a `GoTo` action.
+fn destination_page(document: &Document, item: &Dictionary) -> Option<ObjectId> {
+    let dest = if let Ok(dest) = item.get(b"Dest") {
+        dest
+    } else {
+        let (_, action) = document.dereference(item.get(b"A").ok()?).ok()?;
+        let action = action.as_dict().ok()?;
+        if action.get(b"S").and_then(Object::as_name).ok()? != b"GoTo" {
+            return None;
+        }
+        action.get(b"D").ok()?
+    };
+    resolve_destination(document, dest, 0)
+}
+
+fn resolve_destination(document: &Document, dest: &Object, depth: usize) -> Option<ObjectId> {
+    // named destinations can refer to each other
+    if depth > 8 {
+        return None;
+    }
+    let (_, dest) = document.dereference(dest).ok()?;
+    match dest {
+        Object::Array(array) => array.first()?.as_reference().ok(),
+        // a dictionary with the actual destination array as `D`
+        Object::Dictionary(dict) => resolve_destination(document, dict.get(b"D").ok()?, depth + 1),
+        Object::Name(name) | Object::String(name, _) => {
+            let named = lookup_named_destination(document, name)?;
+            resolve_destination(document, named, depth + 1)
+        }
+        _ => None,
+    }
+}
+
+/// Lookup a named destination in the legacy `Dests` dictionary
+/// or the `Dests` name tree.
+fn lookup_named_destination<'a>(document: &'a Document, name: &[u8]) -> Option<&'a Object> {
+    let catalog = document.catalog().ok()?;
+    if let Ok((_, dests)) = catalog
+        .get(b"Dests")
+        .and_then(|dests| document.dereference(dests))
+    {
+        if let Ok(dest) = dests.as_dict().and_then(|dests| dests.get(name)) {
+            return Some(dest);
+        }
+    }
+    let (_, names) = document.dereference(catalog.get(b"Names").ok()?).ok()?;
+    let (_, tree) = document
+        .dereference(names.as_dict().ok()?.get(b"Dests").ok()?)
+        .ok()?;
+    lookup_name_tree(document, tree.as_dict().ok()?, name, 0)
+}
+
+fn lookup_name_tree<'a>(
+    document: &'a Document,
+    node: &'a Dictionary,
+    name: &[u8],
+    depth: usize,
+) -> Option<&'a Object> {
+    if depth > 32 {
+        return None;
+    }
+    if let Ok(names) = node.get(b"Names").and_then(Object::as_array) {
+        for pair in names.chunks_exact(2) {
+            if pair[0].as_str().ok() == Some(name) {
+                return Some(&pair[1]);
+            }
+        }
+    }
+    let kids = node.get(b"Kids").and_then(Object::as_array).ok()?;
+    kids.iter().find_map(|kid| {
+        let (_, kid) = document.dereference(kid).ok()?;
+        lookup_name_tree(document, kid.as_dict().ok()?, name, depth + 1)
+    })
+}
+
+/// Replace the outline of `document` with the given entries.
+///
+/// Top level entries are shown expanded, nested ones collapsed.
+pub fn add_outline(document: &mut Document, entries: &[OutlineEntry]) -> Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let pages = document
+        .get_pages()
+        .into_values()
+        .collect::<Vec<ObjectId>>();
+
+    let root_id = document.new_object_id();
+    let (first, last) = add_outline_items(document, root_id, entries, &pages)?;
+    let root = dictionary! {
+        "Type" => "Outlines",
+        "First" => first,
+        "Last" => last,
+        "Count" => entries.len() as i64,
+    };
+    document.objects.insert(root_id, Object::Dictionary(root));
+
+    let catalog_id = document
+        .trailer
+        .get(b"Root")
+        .and_then(Object::as_reference)
+        .map_err(|e| eyre!("Document has no catalog: {:?}", e))?;
+    let catalog = document
+        .get_object_mut(catalog_id)
+        .and_then(Object::as_dict_mut)
+        .map_err(|e| eyre!("Catalog is not a dictionary: {:?}", e))?;
+    catalog.set("Outlines", root_id);
+    catalog.set("PageMode", "UseOutlines");
+    Ok(())
+}
+
+fn add_outline_items(
+    document: &mut Document,
+    parent: ObjectId,
+    entries: &[OutlineEntry],
+    pages: &[ObjectId],
+) -> Result<(ObjectId, ObjectId)> {
+    let ids = entries
+        .iter()
+        .map(|_| document.new_object_id())
+        .collect::<Vec<ObjectId>>();
+
+    for (idx, entry) in entries.iter().enumerate() {
+        let page = pages.get(entry.page).ok_or_else(|| {
+            eyre!(
+                "Outline entry >{}< points to page {} of {}",
+                entry.title,
+                entry.page + 1,
+                pages.len()
+            )
+        })?;
+        let mut item = dictionary! {
+            "Title" => encode_text_string(&entry.title),
+            "Parent" => parent,
+            "Dest" => vec![Object::Reference(*page), "Fit".into()],
+        };
+        if idx > 0 {
+            item.set("Prev", ids[idx - 1]);
+        }
+        if let Some(next) = ids.get(idx + 1) {
+            item.set("Next", *next);
+        }
+        if !entry.children.is_empty() {
+            let (first, last) = add_outline_items(document, ids[idx], &entry.children, pages)?;
+            item.set("First", first);
+            item.set("Last", last);
+            // negative, so the nested items are collapsed
+            item.set("Count", -(entry.children.len() as i64));
+        }
+        document.objects.insert(ids[idx], Object::Dictionary(item));
+    }
+
+    let first = *ids.first().expect("Entries are never empty. qed");
+    let last = *ids.last().expect("Entries are never empty. qed");
+    Ok((first, last))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A document with `count` empty pages.
+    fn document(count: usize) -> Document {
+        let mut document = Document::with_version("1.5");
+        let pages = document.new_object_id();
+        let kids = (0..count)
+            .map(|_| {
+                document
+                    .add_object(dictionary! {
+                        "Type" => "Page",
+                        "Parent" => pages,
+                        "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+                    })
+                    .into()
+            })
+            .collect::<Vec<Object>>();
+        document.objects.insert(
+            pages,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => count as i64,
+            }),
+        );
+        let catalog = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages,
+        });
+        document.trailer.set("Root", catalog);
+        document
+    }
+
+    #[test]
+    fn round_trip() {
+        // relative to the receipts, shifted past the summary page
+        let mut record = OutlineEntry::new("2021-03-04 – Cab Corp – Taxi", 0);
+        record.children.push(OutlineEntry::new("Invoice", 1));
+        let record = record.offset(1);
+        let entries = vec![
+            OutlineEntry::new("Summary", 0),
+            record,
+            // a record without receipts points to the summary
+            OutlineEntry::new("2021-03-05 – Café – Tip", 0),
+        ];
+        let mut document = document(3);
+        add_outline(&mut document, &entries).unwrap();
+
+        let extracted = extract_outline(&document);
+        let flat = |entries: &[OutlineEntry]| {
+            entries
+                .iter()
+                .map(|entry| (entry.title.clone(), entry.page, entry.children.len()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(flat(&extracted), flat(&entries));
+        assert_eq!(
+            flat(&extracted[1].children),
+            vec![("Invoice".to_owned(), 2, 0)]
+        );
+
+        // entries past the last page are rejected
+        let past = [OutlineEntry::new("Missing", 3)];
+        assert!(add_outline(&mut document, &past).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+//! Stamp additional content on top of existing pages.
+//!
+//! The content is rendered with `printpdf` into a scratch document with
+//! one page per stamped page, each of which is then imported as form xobject
+//! and drawn after the original page content.
+
+use super::constants::TTF_REGULAR;
+use super::helper::flush_pdf_ops;
+use super::links::add_link;
+use super::page::{inherited_attribute, PageGeometry};
+use super::types::*;
+use crate::errors::*;
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use printpdf::{PdfDocument, PdfLayerReference};
+
+/// Drawing surface for a single stamped page, in display coordinates.
+pub struct Canvas {
+    pub layer: PdfLayerReference,
+    pub font: IndirectFontRef,
+    pub width: Pt,
+    pub height: Pt,
+    /// Zero based index of the stamped page within the document.
+    pub page: usize,
+    links: Vec<(Rect, usize)>,
+}
+
+impl Canvas {
+    /// Turn the area `rect` into a link to the page with index `target`.
+    pub fn link(&mut self, rect: Rect, target: usize) {
+        self.links.push((rect, target));
+    }
+}
+
+/// Stamp the content drawn by `draw` onto the pages with the given
+/// zero based indices.
+pub fn overlay<F>(document: &mut Document, pages: &[usize], mut draw: F) -> Result<()>
+where
+    F: FnMut(&mut Canvas) -> Result<()>,
+{
+    if pages.is_empty() {
+        return Ok(());
+    }
+
+    let page_ids = document
+        .get_pages()
+        .into_values()
+        .collect::<Vec<ObjectId>>();
+    let targets = pages
+        .iter()
+        .map(|idx| {
+            let id = *page_ids
+                .get(*idx)
+                .ok_or_else(|| eyre!("Can not stamp page {}, out of bounds", idx + 1))?;
+            Ok((*idx, id, PageGeometry::of(document, id)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (_, _, geometry) = targets[0];
+    let (doc, page1, layer1) = PdfDocument::new(
+        "Overlay",
+        Mm::from(geometry.width()),
+        Mm::from(geometry.height()),
+        "Overlay",
+    );
+    let font = doc.add_external_font(TTF_REGULAR)?;
+
+    let mut links = Vec::with_capacity(targets.len());
+    for (n, (idx, _, geometry)) in targets.iter().enumerate() {
+        let (page, layer) = if n == 0 {
+            (page1, layer1)
+        } else {
+            doc.add_page(
+                Mm::from(geometry.width()),
+                Mm::from(geometry.height()),
+                "Overlay",
+            )
+        };
+        let mut canvas = Canvas {
+            layer: doc.get_page(page).get_layer(layer),
+            font: font.clone(),
+            width: geometry.width(),
+            height: geometry.height(),
+            page: *idx,
+            links: Vec::new(),
+        };
+        draw(&mut canvas)?;
+        links.push(canvas.links);
+    }
+
+    let mut stamps = flush_pdf_ops(doc)?;
+    renumber_after(document, &mut stamps);
+
+    let stamp_pages = stamps.get_pages().into_values().collect::<Vec<ObjectId>>();
+    for ((stamp_page, (_, page, geometry)), links) in
+        stamp_pages.into_iter().zip(targets).zip(links)
+    {
+        let content = stamps.get_page_content(stamp_page)?;
+        let resources = stamps
+            .get_dictionary(stamp_page)
+            .and_then(|stamp_page| stamp_page.get(b"Resources"))
+            .cloned()
+            .unwrap_or_else(|_| Object::Dictionary(Dictionary::new()));
+        let form = Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Form",
+                "BBox" => vec![0.into(), 0.into(), geometry.width().0.into(), geometry.height().0.into()],
+                "Matrix" => geometry.matrix().iter().map(|x| Object::Real(*x)).collect::<Vec<_>>(),
+                "Resources" => resources,
+            },
+            content,
+        );
+        let form = document.add_object(form);
+        draw_xobjects(document, page, &[(form, IDENTITY)])?;
+
+        for (rect, target) in links {
+            let target = *page_ids
+                .get(target)
+                .ok_or_else(|| eyre!("Link target page {} is out of bounds", target + 1))?;
+            add_link(document, page, geometry.rect_to_user_space(rect), target)?;
+        }
+    }
+
+    adopt_shared_objects(document, stamps);
+    Ok(())
+}
+
+/// Render the content drawn by `draw` into a new form xobject of the given size.
+pub fn form_xobject<F>(document: &mut Document, width: Pt, height: Pt, draw: F) -> Result<ObjectId>
+where
+    F: FnOnce(&mut Canvas) -> Result<()>,
+{
+    let (doc, page, layer) = PdfDocument::new("Form", Mm::from(width), Mm::from(height), "Form");
+    let mut canvas = Canvas {
+        layer: doc.get_page(page).get_layer(layer),
+        font: doc.add_external_font(TTF_REGULAR)?,
+        width,
+        height,
+        page: 0,
+        links: Vec::new(),
+    };
+    draw(&mut canvas)?;
+
+    let mut scratch = flush_pdf_ops(doc)?;
+    renumber_after(document, &mut scratch);
+    let page = *scratch
+        .get_pages()
+        .values()
+        .next()
+        .ok_or_else(|| eyre!("Rendered form has no page"))?;
+    let content = scratch.get_page_content(page)?;
+    let resources = scratch
+        .get_dictionary(page)
+        .and_then(|page| page.get(b"Resources"))
+        .cloned()
+        .unwrap_or_else(|_| Object::Dictionary(Dictionary::new()));
+    let form = Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), width.0.into(), height.0.into()],
+            "Resources" => resources,
+        },
+        content,
+    );
+    adopt_shared_objects(document, scratch);
+    Ok(document.add_object(form))
+}
+
+/// Renumber the objects of `scratch` so they do not collide with those of `document`.
+pub(super) fn renumber_after(document: &mut Document, scratch: &mut Document) {
+    let start = document
+        .objects
+        .keys()
+        .map(|id| id.0)
+        .max()
+        .unwrap_or_default()
+        .max(document.max_id)
+        + 1;
+    scratch.renumber_objects_with(start);
+    document.max_id = scratch.max_id;
+}
+
+/// Move the shared resources of a rendered scratch document, i.e. fonts, into `document`.
+pub(super) fn adopt_shared_objects(document: &mut Document, scratch: Document) {
+    for (id, object) in scratch.objects {
+        match object.type_name().unwrap_or("") {
+            "Catalog" | "Pages" | "Page" => {}
+            _ => {
+                document.objects.insert(id, object);
+            }
+        }
+    }
+}
+
+/// Transformation matrix which keeps the coordinates as they are.
+pub(super) const IDENTITY: [f64; 6] = [1., 0., 0., 1., 0., 0.];
+
+/// Draw the form xobjects on top of the existing content of `page`, each
+/// transformed by its matrix.
+pub(super) fn draw_xobjects(
+    document: &mut Document,
+    page: ObjectId,
+    xobjects: &[(ObjectId, [f64; 6])],
+) -> Result<()> {
+    // copy the effective resources, shared or inherited ones stay untouched
+    let mut resources = inherited_attribute(document, page, b"Resources")
+        .and_then(|resources| resources.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    let mut names = resources
+        .get(b"XObject")
+        .and_then(|names| document.dereference(names))
+        .and_then(|(_, names)| names.as_dict())
+        .cloned()
+        .unwrap_or_default();
+    let mut drawing = String::from("\nQ\n");
+    for (xobject, [a, b, c, d, e, f]) in xobjects {
+        let name = (0..)
+            .map(|n| format!("Fm{}", n))
+            .find(|name| !names.has(name.as_bytes()))
+            .expect("Infinite iterator yields a free name. qed");
+        names.set(name.clone(), *xobject);
+        drawing += &format!("q {} {} {} {} {} {} cm /{} Do Q\n", a, b, c, d, e, f, name);
+    }
+    resources.set("XObject", names);
+
+    let contents = document
+        .get_dictionary(page)
+        .and_then(|page| page.get(b"Contents"))
+        .and_then(|contents| document.dereference(contents))
+        .map(|(id, contents)| match (id, contents) {
+            (_, Object::Array(contents)) => contents.clone(),
+            (Some(id), _) => vec![Object::Reference(id)],
+            (None, _) => Vec::new(),
+        })
+        .unwrap_or_default();
+
+    // isolate the graphics state of the original content
+    let save = document.add_object(Stream::new(Dictionary::new(), b"q\n".to_vec()));
+    let restore = document.add_object(Stream::new(Dictionary::new(), drawing.into_bytes()));
+    let contents = std::iter::once(Object::Reference(save))
+        .chain(contents)
+        .chain(std::iter::once(Object::Reference(restore)))
+        .collect::<Vec<Object>>();
+
+    let page = document
+        .get_object_mut(page)
+        .and_then(Object::as_dict_mut)
+        .map_err(|e| eyre!("Page is not a dictionary: {:?}", e))?;
+    page.set("Resources", resources);
+    page.set("Contents", contents);
+    Ok(())
+}
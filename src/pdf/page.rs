@@ -0,0 +1,206 @@
+//! Page level helpers for existing, i.e. loaded or combined, documents.
+
+use super::types::*;
+use crate::errors::*;
+use crate::types::PageRange;
+
+use lopdf::{Document, Object, ObjectId};
+
+/// Lookup an inheritable page attribute, i.e. `Resources`, `MediaBox`,
+/// `CropBox` or `Rotate`, which might be defined by any ancestor.
+pub fn inherited_attribute<'a>(
+    document: &'a Document,
+    page: ObjectId,
+    key: &[u8],
+) -> Option<&'a Object> {
+    let mut node = document.get_dictionary(page).ok()?;
+    // bounded, malformed documents might contain cycles
+    for _ in 0..32 {
+        if let Ok(value) = node.get(key) {
+            return document.dereference(value).ok().map(|(_, value)| value);
+        }
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = document.get_dictionary(parent).ok()?;
+    }
+    None
+}
+
+/// Append the annotation `annotation` to the annotations of `page`.
+pub fn add_annotation(document: &mut Document, page: ObjectId, annotation: ObjectId) -> Result<()> {
+    // the annotations array is either inline or an indirect object on its own
+    let annots = document
+        .get_dictionary(page)
+        .and_then(|page| page.get(b"Annots"))
+        .and_then(Object::as_reference)
+        .ok();
+    let annots = if let Some(annots) = annots {
+        document.get_object_mut(annots)
+    } else {
+        let page = document
+            .get_object_mut(page)
+            .and_then(Object::as_dict_mut)
+            .map_err(|e| eyre!("Page is not a dictionary: {:?}", e))?;
+        if !page.has(b"Annots") {
+            page.set("Annots", Vec::<Object>::new());
+        }
+        page.get_mut(b"Annots")
+    };
+    annots
+        .and_then(Object::as_array_mut)
+        .map_err(|e| eyre!("Page annotations are not an array: {:?}", e))?
+        .push(Object::Reference(annotation));
+    Ok(())
+}
+
+/// Keep only the pages with one based numbers contained in `ranges`.
+pub fn select_pages(document: &mut Document, ranges: &[PageRange]) -> Result<()> {
+    let pages = document.get_pages();
+    let count = pages.len();
+    for range in ranges {
+        if range.first > count || range.last.is_some_and(|last| last > count) {
+            bail!("Page selection exceeds the {} pages of the document", count);
+        }
+    }
+    let dropped = pages
+        .keys()
+        .copied()
+        .filter(|number| !ranges.iter().any(|range| range.contains(*number as usize)))
+        .collect::<Vec<u32>>();
+    document.delete_pages(&dropped);
+    Ok(())
+}
+
+/// Turn all pages clockwise by `degrees`, a multiple of 90.
+pub fn rotate_pages(document: &mut Document, degrees: u16) -> Result<()> {
+    for page in document.get_pages().into_values() {
+        let rotation = inherited_attribute(document, page, b"Rotate")
+            .and_then(|rotation| rotation.as_i64().ok())
+            .unwrap_or(0);
+        let rotation = (rotation + degrees as i64).rem_euclid(360);
+        document
+            .get_object_mut(page)
+            .and_then(Object::as_dict_mut)
+            .map_err(|e| eyre!("Page is not a dictionary: {:?}", e))?
+            .set("Rotate", rotation);
+    }
+    Ok(())
+}
+
+/// Geometry of a page as it is displayed.
+///
+/// Accounts for the offset of the `MediaBox` and the `Rotate` attribute,
+/// so content can be placed in display coordinates.
+#[derive(Debug, Clone, Copy)]
+pub struct PageGeometry {
+    /// `[llx, lly, urx, ury]` of the media box in user space.
+    media_box: [f64; 4],
+    /// Clockwise rotation in degrees, one of `0`, `90`, `180` or `270`.
+    rotation: i64,
+}
+
+impl PageGeometry {
+    pub fn of(document: &Document, page: ObjectId) -> Result<Self> {
+        let media_box = inherited_attribute(document, page, b"MediaBox")
+            .and_then(|media_box| media_box.as_array().ok())
+            .map(|media_box| {
+                media_box
+                    .iter()
+                    .filter_map(|x| document.dereference(x).ok())
+                    .filter_map(|(_, x)| x.as_float().ok())
+                    .collect::<Vec<f64>>()
+            })
+            .filter(|media_box| media_box.len() == 4)
+            .ok_or_else(|| eyre!("Page {:?} has no valid media box", page))?;
+        let rotation = inherited_attribute(document, page, b"Rotate")
+            .and_then(|rotation| rotation.as_i64().ok())
+            .unwrap_or(0)
+            .rem_euclid(360);
+        Ok(Self::new(
+            [
+                media_box[0].min(media_box[2]),
+                media_box[1].min(media_box[3]),
+                media_box[0].max(media_box[2]),
+                media_box[1].max(media_box[3]),
+            ],
+            rotation - rotation % 90,
+        ))
+    }
+
+    pub fn new(media_box: [f64; 4], rotation: i64) -> Self {
+        Self {
+            media_box,
+            rotation,
+        }
+    }
+
+    fn is_sideways(&self) -> bool {
+        self.rotation == 90 || self.rotation == 270
+    }
+
+    /// Width as displayed.
+    pub fn width(&self) -> Pt {
+        let [llx, lly, urx, ury] = self.media_box;
+        Pt(if self.is_sideways() {
+            ury - lly
+        } else {
+            urx - llx
+        })
+    }
+
+    /// Height as displayed.
+    pub fn height(&self) -> Pt {
+        let [llx, lly, urx, ury] = self.media_box;
+        Pt(if self.is_sideways() {
+            urx - llx
+        } else {
+            ury - lly
+        })
+    }
+
+    /// Transformation matrix from display coordinates to user space.
+    pub fn matrix(&self) -> [f64; 6] {
+        let [llx, lly, urx, ury] = self.media_box;
+        match self.rotation {
+            90 => [0., 1., -1., 0., urx, lly],
+            180 => [-1., 0., 0., -1., urx, ury],
+            270 => [0., -1., 1., 0., llx, ury],
+            _ => [1., 0., 0., 1., llx, lly],
+        }
+    }
+
+    /// Transformation matrix from user space to display coordinates.
+    pub fn inverse_matrix(&self) -> [f64; 6] {
+        let [a, b, c, d, e, f] = self.matrix();
+        let det = a * d - b * c;
+        [
+            d / det,
+            -b / det,
+            -c / det,
+            a / det,
+            (c * f - d * e) / det,
+            (b * e - a * f) / det,
+        ]
+    }
+
+    /// `[llx, lly, urx, ury]` of the media box in user space.
+    pub fn media_box(&self) -> [f64; 4] {
+        self.media_box
+    }
+
+    /// Convert a point in display coordinates to user space.
+    pub fn to_user_space(self, point: Point) -> Point {
+        let [a, b, c, d, e, f] = self.matrix();
+        Point {
+            x: Pt(a * point.x.0 + c * point.y.0 + e),
+            y: Pt(b * point.x.0 + d * point.y.0 + f),
+        }
+    }
+
+    /// Convert a rectangle in display coordinates to user space.
+    pub fn rect_to_user_space(self, rect: Rect) -> Rect {
+        Rect::from_corners(
+            self.to_user_space(rect.lower_left),
+            self.to_user_space(rect.upper_right),
+        )
+    }
+}
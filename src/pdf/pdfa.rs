@@ -0,0 +1,493 @@
+//! PDF/A-3b archival output.
+//!
+//! Receipts are checked for constructs which can not be fixed up
+//! automatically, the combined document is then converted in place:
+//! sRGB output intent, XMP metadata matching the document information
+//! dictionary, printable annotations and a file identifier.
+
+use super::constants::ICC_SRGB;
+use super::info::{pdf_date, producer};
+use super::strings::{decode_text_string, encode_text_string};
+use crate::errors::*;
+
+use lopdf::content::Content;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Actions which are not permitted in PDF/A documents.
+const FORBIDDEN_ACTIONS: &[&[u8]] = &[
+    b"Launch",
+    b"Sound",
+    b"Movie",
+    b"ResetForm",
+    b"ImportData",
+    b"Hide",
+    b"SetOCGState",
+    b"Rendition",
+    b"Trans",
+    b"GoTo3DView",
+    b"JavaScript",
+];
+
+/// Annotations which are not permitted in PDF/A documents.
+const FORBIDDEN_ANNOTATIONS: &[&[u8]] = &[b"3D", b"Sound", b"Screen", b"Movie"];
+
+/// Blend modes defined by the PDF specification.
+const STANDARD_BLEND_MODES: &[&[u8]] = &[
+    b"Normal",
+    b"Compatible",
+    b"Multiply",
+    b"Screen",
+    b"Overlay",
+    b"Darken",
+    b"Lighten",
+    b"ColorDodge",
+    b"ColorBurn",
+    b"HardLight",
+    b"SoftLight",
+    b"Difference",
+    b"Exclusion",
+    b"Hue",
+    b"Saturation",
+    b"Color",
+    b"Luminosity",
+];
+
+/// Check that `document` can be made PDF/A-3b conformant by
+/// [`convert_to_pdfa`], i.e. fonts are embedded, there is neither
+/// encryption nor interactive content and all colors are RGB or gray.
+pub fn check_pdfa_compatible(document: &Document) -> Result<()> {
+    if document.trailer.has(b"Encrypt") {
+        bail!("Encrypted documents can not be archived");
+    }
+
+    for (id, object) in document.objects.iter() {
+        let dict = match object {
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => {
+                check_stream(document, *id, stream)?;
+                &stream.dict
+            }
+            _ => continue,
+        };
+        match dict.get(b"Type").and_then(Object::as_name).unwrap_or(b"") {
+            b"Font" => check_font(document, dict)?,
+            b"ExtGState" => check_graphics_state(dict)?,
+            b"Action" => check_action(dict)?,
+            b"Annot" => check_annotation(dict)?,
+            b"" => {
+                // the type is optional for actions and annotations
+                if dict.has(b"S") || dict.has(b"JS") {
+                    check_action(dict)?;
+                }
+                if dict.has(b"Subtype") && dict.has(b"Rect") {
+                    check_annotation(dict)?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (n, page) in document.get_pages() {
+        let content = document
+            .get_and_decode_page_content(page)
+            .map_err(|e| eyre!("Failed to decode content of page {}: {:?}", n, e))?;
+        check_content(&content).wrap_err_with(|| eyre!("Page {} is not archivable", n))?;
+    }
+    Ok(())
+}
+
+fn check_stream(document: &Document, id: ObjectId, stream: &Stream) -> Result<()> {
+    if stream.dict.has(b"F") || stream.dict.has(b"FFilter") {
+        bail!("Stream {:?} references external content", id);
+    }
+    let filters = match stream.dict.get(b"Filter") {
+        Ok(Object::Name(name)) => vec![name.as_slice()],
+        Ok(Object::Array(filters)) => filters
+            .iter()
+            .filter_map(|filter| filter.as_name().ok())
+            .collect(),
+        _ => Vec::new(),
+    };
+    if filters.contains(&b"LZWDecode".as_ref()) {
+        bail!("Stream {:?} uses the LZW compression", id);
+    }
+
+    match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+        Ok(b"Image") => {
+            let colorspace = stream
+                .dict
+                .get(b"ColorSpace")
+                .and_then(|colorspace| document.dereference(colorspace))
+                .map(|(_, colorspace)| colorspace);
+            if let Ok(Object::Name(name)) = colorspace {
+                if name == b"DeviceCMYK" {
+                    bail!("Image {:?} uses CMYK colors", id);
+                }
+            }
+            if stream.dict.has(b"Alternates") || stream.dict.has(b"OPI") {
+                bail!("Image {:?} has alternates", id);
+            }
+        }
+        Ok(b"Form") => {
+            if stream.dict.has(b"OPI") || stream.dict.has(b"Ref") {
+                bail!("Form {:?} references external content", id);
+            }
+            if let Ok(content) = stream
+                .decompressed_content()
+                .or_else(|_| Ok::<_, lopdf::Error>(stream.content.clone()))
+                .and_then(|content| Content::decode(&content))
+            {
+                check_content(&content)
+                    .wrap_err_with(|| eyre!("Form {:?} is not archivable", id))?;
+            }
+        }
+        Ok(b"PS") => bail!("Stream {:?} contains postscript", id),
+        _ => {}
+    }
+    Ok(())
+}
+
+fn check_content(content: &Content) -> Result<()> {
+    for operation in content.operations.iter() {
+        match operation.operator.as_str() {
+            "k" | "K" => bail!("CMYK colors are used"),
+            "cs" | "CS" => {
+                let name = operation.operands.first().and_then(|x| x.as_name().ok());
+                if name == Some(b"DeviceCMYK") {
+                    bail!("CMYK colors are used");
+                }
+            }
+            "PS" => bail!("Postscript is used"),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn check_font(document: &Document, font: &Dictionary) -> Result<()> {
+    let name = font
+        .get(b"BaseFont")
+        .and_then(Object::as_name_str)
+        .unwrap_or("<unnamed>");
+    let subtype = font
+        .get(b"Subtype")
+        .and_then(Object::as_name)
+        .unwrap_or(b"");
+    match subtype {
+        // glyphs are defined by content streams
+        b"Type3" => return Ok(()),
+        // embedding is checked for the descendant font
+        b"Type0" => return Ok(()),
+        _ => {}
+    }
+    let descriptor = font
+        .get(b"FontDescriptor")
+        .and_then(|descriptor| document.dereference(descriptor))
+        .and_then(|(_, descriptor)| descriptor.as_dict());
+    let embedded = descriptor
+        .map(|descriptor| {
+            descriptor.has(b"FontFile")
+                || descriptor.has(b"FontFile2")
+                || descriptor.has(b"FontFile3")
+        })
+        .unwrap_or(false);
+    if !embedded {
+        bail!("Font {} is not embedded", name);
+    }
+    Ok(())
+}
+
+fn check_graphics_state(state: &Dictionary) -> Result<()> {
+    if let Ok(transfer) = state.get(b"TR") {
+        bail!("Transfer functions are not permitted: {:?}", transfer);
+    }
+    if let Ok(transfer) = state.get(b"TR2") {
+        if transfer.as_name().ok() != Some(b"Default") {
+            bail!("Transfer functions are not permitted: {:?}", transfer);
+        }
+    }
+    let blend_modes = match state.get(b"BM") {
+        Ok(Object::Name(name)) => vec![name.as_slice()],
+        Ok(Object::Array(names)) => names.iter().filter_map(|x| x.as_name().ok()).collect(),
+        _ => Vec::new(),
+    };
+    for blend_mode in blend_modes {
+        if !STANDARD_BLEND_MODES.contains(&blend_mode) {
+            bail!(
+                "Non-standard blend mode {} is used",
+                String::from_utf8_lossy(blend_mode)
+            );
+        }
+    }
+    Ok(())
+}
+
+fn check_action(action: &Dictionary) -> Result<()> {
+    let kind = action.get(b"S").and_then(Object::as_name).unwrap_or(b"");
+    if FORBIDDEN_ACTIONS.contains(&kind) || action.has(b"JS") {
+        bail!(
+            "Interactive {} actions are not permitted",
+            String::from_utf8_lossy(kind)
+        );
+    }
+    Ok(())
+}
+
+fn check_annotation(annotation: &Dictionary) -> Result<()> {
+    let subtype = annotation
+        .get(b"Subtype")
+        .and_then(Object::as_name)
+        .unwrap_or(b"");
+    if FORBIDDEN_ANNOTATIONS.contains(&subtype) {
+        bail!(
+            "{} annotations are not permitted",
+            String::from_utf8_lossy(subtype)
+        );
+    }
+    if subtype != b"Link" && subtype != b"Popup" && !annotation.has(b"AP") {
+        bail!(
+            "{} annotation lacks an appearance",
+            String::from_utf8_lossy(subtype)
+        );
+    }
+    Ok(())
+}
+
+/// Convert `document` into PDF/A-3b, which must have been assembled
+/// from documents which passed [`check_pdfa_compatible`].
+pub fn convert_to_pdfa(document: &mut Document) -> Result<()> {
+    document.version = "1.7".to_owned();
+
+    let producer = producer();
+    let now = pdf_date(chrono::Local::now());
+    let info_id = match document.trailer.get(b"Info").and_then(Object::as_reference) {
+        Ok(id) => id,
+        Err(_) => {
+            let id = document.add_object(Dictionary::new());
+            document.trailer.set("Info", id);
+            id
+        }
+    };
+    let info = document
+        .get_object_mut(info_id)
+        .and_then(Object::as_dict_mut)
+        .map_err(|e| eyre!("Document information is not a dictionary: {:?}", e))?;
+    for (key, default) in [
+        ("Producer", producer.as_str()),
+        ("Creator", producer.as_str()),
+        ("CreationDate", now.as_str()),
+        ("ModDate", now.as_str()),
+    ] {
+        if !info.has(key.as_bytes()) {
+            info.set(key, encode_text_string(default));
+        }
+    }
+    let info = info.clone();
+
+    for object in document.objects.values_mut() {
+        match object {
+            Object::Dictionary(dict) if dict.has(b"Subtype") && dict.has(b"Rect") => {
+                // annotations must be printed, hidden ones are not permitted
+                let flags = dict.get(b"F").and_then(Object::as_i64).unwrap_or(0);
+                dict.set("F", (flags | 4) & !(1 | 2 | 32));
+            }
+            // interpolation of images is not permitted
+            Object::Stream(stream) if stream.dict.has(b"Interpolate") => {
+                stream.dict.set("Interpolate", false);
+            }
+            _ => {}
+        }
+    }
+
+    let profile = document.add_object(Stream::new(dictionary! { "N" => 3 }, ICC_SRGB.to_vec()));
+    let intent = document.add_object(dictionary! {
+        "Type" => "OutputIntent",
+        "S" => "GTS_PDFA1",
+        "OutputConditionIdentifier" => Object::string_literal("sRGB IEC61966-2.1"),
+        "Info" => Object::string_literal("sRGB IEC61966-2.1"),
+        "RegistryName" => Object::string_literal("http://www.color.org"),
+        "DestOutputProfile" => profile,
+    });
+
+    let mut metadata = Stream::new(
+        dictionary! {
+            "Type" => "Metadata",
+            "Subtype" => "XML",
+        },
+        xmp_metadata(&info).into_bytes(),
+    );
+    // must stay readable without decoding
+    metadata.allows_compression = false;
+    let metadata = document.add_object(metadata);
+
+    let catalog_id = document
+        .trailer
+        .get(b"Root")
+        .and_then(Object::as_reference)
+        .map_err(|e| eyre!("Document has no catalog: {:?}", e))?;
+    let catalog = document
+        .get_object_mut(catalog_id)
+        .and_then(Object::as_dict_mut)
+        .map_err(|e| eyre!("Catalog is not a dictionary: {:?}", e))?;
+    catalog.set("OutputIntents", vec![Object::Reference(intent)]);
+    catalog.set("Metadata", metadata);
+    for key in [b"AA".as_ref(), b"AcroForm", b"OCProperties", b"Perms"] {
+        catalog.remove(key);
+    }
+
+    let mut hasher = DefaultHasher::new();
+    now.hash(&mut hasher);
+    document.objects.len().hash(&mut hasher);
+    let first = hasher.finish();
+    producer.hash(&mut hasher);
+    let second = hasher.finish();
+    let id = [first.to_be_bytes(), second.to_be_bytes()].concat();
+    document.trailer.set(
+        "ID",
+        vec![
+            Object::String(id.clone(), lopdf::StringFormat::Hexadecimal),
+            Object::String(id, lopdf::StringFormat::Hexadecimal),
+        ],
+    );
+    Ok(())
+}
+
+/// Convert a pdf date string into the XMP date format.
+fn xmp_date(date: &str) -> Option<String> {
+    let date = date.strip_prefix("D:").unwrap_or(date);
+    let digits = date
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect::<String>();
+    if digits.len() < 4 {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>, default: &'static str| {
+        digits.get(range).unwrap_or(default).to_owned()
+    };
+    let offset = match date[digits.len()..].chars().next() {
+        Some(sign @ ('+' | '-')) => {
+            let offset = date[digits.len() + 1..].replace('\'', "");
+            format!(
+                "{}{}:{}",
+                sign,
+                offset.get(0..2).unwrap_or("00"),
+                offset.get(2..4).unwrap_or("00")
+            )
+        }
+        _ => "Z".to_owned(),
+    };
+    Some(format!(
+        "{}-{}-{}T{}:{}:{}{}",
+        field(0..4, "0000"),
+        field(4..6, "01"),
+        field(6..8, "01"),
+        field(8..10, "00"),
+        field(10..12, "00"),
+        field(12..14, "00"),
+        offset
+    ))
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Create the XMP metadata packet, mirroring the document information dictionary.
+fn xmp_metadata(info: &Dictionary) -> String {
+    let entry = |key: &[u8]| {
+        info.get(key)
+            .and_then(Object::as_str)
+            .map(decode_text_string)
+            .ok()
+    };
+
+    let mut properties = vec![
+        "<pdfaid:part>3</pdfaid:part>".to_owned(),
+        "<pdfaid:conformance>B</pdfaid:conformance>".to_owned(),
+    ];
+    if let Some(title) = entry(b"Title") {
+        properties.push(format!(
+            "<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>",
+            xml_escape(&title)
+        ));
+    }
+    if let Some(author) = entry(b"Author") {
+        properties.push(format!(
+            "<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>",
+            xml_escape(&author)
+        ));
+    }
+    if let Some(subject) = entry(b"Subject") {
+        properties.push(format!(
+            "<dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>",
+            xml_escape(&subject)
+        ));
+    }
+    if let Some(keywords) = entry(b"Keywords") {
+        properties.push(format!(
+            "<pdf:Keywords>{}</pdf:Keywords>",
+            xml_escape(&keywords)
+        ));
+    }
+    if let Some(producer) = entry(b"Producer") {
+        properties.push(format!(
+            "<pdf:Producer>{}</pdf:Producer>",
+            xml_escape(&producer)
+        ));
+    }
+    if let Some(creator) = entry(b"Creator") {
+        properties.push(format!(
+            "<xmp:CreatorTool>{}</xmp:CreatorTool>",
+            xml_escape(&creator)
+        ));
+    }
+    if let Some(date) = entry(b"CreationDate").as_deref().and_then(xmp_date) {
+        properties.push(format!("<xmp:CreateDate>{}</xmp:CreateDate>", date));
+    }
+    if let Some(date) = entry(b"ModDate").as_deref().and_then(xmp_date) {
+        properties.push(format!("<xmp:ModifyDate>{}</xmp:ModifyDate>", date));
+    }
+
+    format!(
+        r#"<?xpacket begin="{bom}" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description rdf:about=""
+ xmlns:pdfaid="http://www.aiim.org/pdfa/ns/id/"
+ xmlns:dc="http://purl.org/dc/elements/1.1/"
+ xmlns:pdf="http://ns.adobe.com/pdf/1.3/"
+ xmlns:xmp="http://ns.adobe.com/xap/1.0/">
+{properties}
+</rdf:Description>
+</rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#,
+        bom = '\u{feff}',
+        properties = properties.join("\n"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        assert_eq!(
+            xmp_date("D:20210301120000+01'00'").as_deref(),
+            Some("2021-03-01T12:00:00+01:00")
+        );
+        assert_eq!(
+            xmp_date("D:20210301").as_deref(),
+            Some("2021-03-01T00:00:00Z")
+        );
+        assert_eq!(xmp_date("garbage"), None);
+    }
+}
@@ -0,0 +1,326 @@
+//! Corrections for photographed receipts: orientation, skew and the
+//! background around the paper.
+
+use printpdf::image::{
+    self, imageops::FilterType, DynamicImage, GenericImageView, GrayImage, Rgb, RgbImage,
+};
+
+/// Largest skew corrected, in degrees.
+const MAX_SKEW: f64 = 10.;
+/// Longest side of the downscaled copy used for detection.
+const DETECTION_SIZE: u32 = 800;
+
+/// The EXIF orientation of an encoded image, `1` is upright.
+pub(crate) fn orientation(bytes: &[u8]) -> Option<u32> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(bytes))
+        .ok()?;
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)
+}
+
+/// Rotate and mirror the decoded pixels according to the EXIF `orientation`.
+pub(crate) fn orient(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Crop to the paper, which is told apart from the background by being
+/// brighter. Images without a distinct paper area are kept as they are.
+pub(crate) fn auto_crop(image: DynamicImage) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let (small, factor) = detection_copy(&image);
+    let (left, top, right, bottom) = match paper_bounds(&small) {
+        Some(bounds) => bounds,
+        None => return image,
+    };
+
+    // map back with a small margin, the paper edge is blurry
+    let margin = 2;
+    let scale = |v: u32, limit: u32| ((v as f64 * factor).round() as u32).min(limit);
+    let x0 = scale(left.saturating_sub(margin), width);
+    let y0 = scale(top.saturating_sub(margin), height);
+    let x1 = scale(right + 1 + margin, width);
+    let y1 = scale(bottom + 1 + margin, height);
+    let area = (x1 - x0) as f64 * (y1 - y0) as f64 / (width as f64 * height as f64);
+    // nothing to gain, or rather a misdetection
+    if !(0.1..0.95).contains(&area) {
+        return image;
+    }
+    log::debug!(
+        "Cropping image of {}x{} pixels to {}x{} pixels at {},{}",
+        width,
+        height,
+        x1 - x0,
+        y1 - y0,
+        x0,
+        y0
+    );
+    image.crop_imm(x0, y0, x1 - x0, y1 - y0)
+}
+
+/// Inclusive bounds of the bright paper as left, top, right and bottom.
+fn paper_bounds(image: &GrayImage) -> Option<(u32, u32, u32, u32)> {
+    let threshold = super::images::otsu_threshold(image);
+    let (width, height) = image.dimensions();
+    let bright = |x: u32, y: u32| image.get_pixel(x, y).0[0] > threshold;
+    let rows = (0..height)
+        .map(|y| (0..width).filter(|x| bright(*x, y)).count() as f64 / width as f64)
+        .collect::<Vec<_>>();
+    let columns = (0..width)
+        .map(|x| (0..height).filter(|y| bright(x, *y)).count() as f64 / height as f64)
+        .collect::<Vec<_>>();
+    let (top, bottom) = paper_span(&rows)?;
+    let (left, right) = paper_span(&columns)?;
+    Some((left as u32, top as u32, right as u32, bottom as u32))
+}
+
+/// The run of lines around the brightest one, which are mostly bright.
+fn paper_span(profile: &[f64]) -> Option<(usize, usize)> {
+    // bridge the lines of text, which are mostly dark
+    let reach = (profile.len() / 50).max(2);
+    let closed = (0..profile.len())
+        .map(|idx| {
+            profile[idx.saturating_sub(reach)..(idx + reach + 1).min(profile.len())]
+                .iter()
+                .cloned()
+                .fold(0f64, f64::max)
+        })
+        .collect::<Vec<_>>();
+    let (peak, max) = closed
+        .iter()
+        .cloned()
+        .enumerate()
+        .fold(
+            (0, 0f64),
+            |best, (idx, v)| if v > best.1 { (idx, v) } else { best },
+        );
+    if max < 0.1 {
+        return None;
+    }
+    let paper = |v: &f64| *v > max * 0.5;
+    let start = closed[..peak]
+        .iter()
+        .rposition(|v| !paper(v))
+        .map(|idx| idx + 1 + reach)
+        .unwrap_or(0);
+    let end = closed[peak..]
+        .iter()
+        .position(|v| !paper(v))
+        .map(|idx| (peak + idx).saturating_sub(reach + 1))
+        .unwrap_or(profile.len() - 1);
+    Some((start.min(end), end))
+}
+
+/// Rotate lines of text to the horizontal, the skew is the angle maximizing
+/// the variance of the dark pixel count per row.
+pub(crate) fn deskew(image: DynamicImage) -> DynamicImage {
+    let angle = skew(&detection_copy(&image).0);
+    if angle.abs() < 0.2 {
+        return image;
+    }
+    log::debug!("Correcting skew of {:.1}°", angle);
+    let gray = matches!(image, DynamicImage::ImageLuma8(_));
+    let rotated = DynamicImage::ImageRgb8(rotate(&image.to_rgb8(), -angle));
+    if gray {
+        DynamicImage::ImageLuma8(rotated.to_luma8())
+    } else {
+        rotated
+    }
+}
+
+/// Skew of the content in degrees, counter clockwise.
+fn skew(image: &GrayImage) -> f64 {
+    // only the text on the paper, away from its tilted edges
+    let (width, height) = image.dimensions();
+    let (left, top, right, bottom) = paper_bounds(image).unwrap_or((0, 0, width - 1, height - 1));
+    let (inset_x, inset_y) = ((right - left) / 16, (bottom - top) / 16);
+    let paper = image::imageops::crop_imm(
+        image,
+        left + inset_x,
+        top + inset_y,
+        (right - left + 1 - 2 * inset_x).max(1),
+        (bottom - top + 1 - 2 * inset_y).max(1),
+    )
+    .to_image();
+    let image = &paper;
+    let threshold = super::images::otsu_threshold(image);
+    let ink = image
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel.0[0] <= threshold)
+        .map(|(x, y, _)| (x as f64, y as f64))
+        .collect::<Vec<_>>();
+    if ink.is_empty() {
+        return 0.;
+    }
+    let rows = (image.width() + image.height()) as usize * 2;
+    let score = |angle: f64| {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let mut histogram = vec![0u64; rows];
+        for (x, y) in ink.iter() {
+            // counter clockwise skew in image coordinates, y pointing down
+            let row = y * cos + x * sin + (rows / 2) as f64;
+            if let Some(count) = histogram.get_mut(row.max(0.) as usize) {
+                *count += 1;
+            }
+        }
+        histogram
+            .iter()
+            .map(|count| (count * count) as f64)
+            .sum::<f64>()
+    };
+    // coarse search followed by a finer one around the best match
+    let best = |candidates: Vec<f64>| {
+        candidates
+            .into_iter()
+            .map(|angle| (angle, score(angle)))
+            .fold((0., f64::MIN), |best, (angle, score)| {
+                if score > best.1 {
+                    (angle, score)
+                } else {
+                    best
+                }
+            })
+            .0
+    };
+    let steps = (MAX_SKEW * 2.) as i32;
+    let coarse = best((-steps..=steps).map(|step| step as f64 * 0.5).collect());
+    best((-5..=5).map(|step| coarse + step as f64 * 0.1).collect())
+}
+
+/// Rotate counter clockwise by `angle` degrees around the center, keeping
+/// the dimensions and filling the corners with the average border color.
+fn rotate(image: &RgbImage, angle: f64) -> RgbImage {
+    let (width, height) = image.dimensions();
+    let fill = border_color(image);
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (cx, cy) = (width as f64 / 2., height as f64 / 2.);
+    RgbImage::from_fn(width, height, |x, y| {
+        // inverse mapping from destination to source
+        let (dx, dy) = (x as f64 + 0.5 - cx, y as f64 + 0.5 - cy);
+        let sx = dx * cos - dy * sin + cx - 0.5;
+        let sy = dx * sin + dy * cos + cy - 0.5;
+        if sx < 0. || sy < 0. || sx > (width - 1) as f64 || sy > (height - 1) as f64 {
+            return fill;
+        }
+        let (x0, y0) = (sx.floor() as u32, sy.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (fx, fy) = (sx - x0 as f64, sy - y0 as f64);
+        let mut pixel = [0u8; 3];
+        for (channel, value) in pixel.iter_mut().enumerate() {
+            let at = |x, y| image.get_pixel(x, y).0[channel] as f64;
+            let top = at(x0, y0) * (1. - fx) + at(x1, y0) * fx;
+            let bottom = at(x0, y1) * (1. - fx) + at(x1, y1) * fx;
+            *value = (top * (1. - fy) + bottom * fy).round() as u8;
+        }
+        Rgb(pixel)
+    })
+}
+
+fn border_color(image: &RgbImage) -> Rgb<u8> {
+    let (width, height) = image.dimensions();
+    let border = (0..width)
+        .flat_map(|x| [(x, 0), (x, height - 1)])
+        .chain((0..height).flat_map(|y| [(0, y), (width - 1, y)]))
+        .map(|(x, y)| image.get_pixel(x, y).0)
+        .collect::<Vec<_>>();
+    let mut color = [0u8; 3];
+    for (channel, value) in color.iter_mut().enumerate() {
+        let sum = border
+            .iter()
+            .map(|pixel| pixel[channel] as u64)
+            .sum::<u64>();
+        *value = (sum / border.len().max(1) as u64) as u8;
+    }
+    Rgb(color)
+}
+
+/// A small grayscale copy for detection and the factor to scale its
+/// coordinates back up.
+fn detection_copy(image: &DynamicImage) -> (GrayImage, f64) {
+    let (width, height) = image.dimensions();
+    let longest = width.max(height);
+    if longest <= DETECTION_SIZE {
+        return (image.to_luma8(), 1.);
+    }
+    let factor = longest as f64 / DETECTION_SIZE as f64;
+    let small = image.resize(DETECTION_SIZE, DETECTION_SIZE, FilterType::Triangle);
+    (small.to_luma8(), factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dark background with a bright sheet of paper with lines of text.
+    fn receipt(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            let paper =
+                x >= width / 4 && x < width * 3 / 4 && y >= height / 5 && y < height * 4 / 5;
+            if !paper {
+                Rgb([60, 40, 30])
+            } else if y % 20 < 4 && x % 30 < 24 {
+                Rgb([20, 20, 20])
+            } else {
+                Rgb([240, 240, 235])
+            }
+        })
+    }
+
+    #[test]
+    fn orientations() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(3, 2, |x, y| {
+            Rgb([(y * 3 + x) as u8, 0, 0])
+        }));
+        let values = |image: DynamicImage| {
+            let image = image.to_rgb8();
+            (
+                image.dimensions(),
+                image.pixels().map(|p| p.0[0]).collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(
+            values(orient(image.clone(), 1)),
+            ((3, 2), vec![0, 1, 2, 3, 4, 5])
+        );
+        assert_eq!(
+            values(orient(image.clone(), 6)),
+            ((2, 3), vec![3, 0, 4, 1, 5, 2])
+        );
+        assert_eq!(
+            values(orient(image.clone(), 5)),
+            ((2, 3), vec![0, 3, 1, 4, 2, 5])
+        );
+        assert_eq!(
+            values(orient(image.clone(), 8)),
+            ((2, 3), vec![2, 5, 1, 4, 0, 3])
+        );
+        assert_eq!(values(orient(image, 7)), ((2, 3), vec![5, 2, 4, 1, 3, 0]));
+    }
+
+    #[test]
+    fn crop_to_paper() {
+        let cropped = auto_crop(DynamicImage::ImageRgb8(receipt(400, 500)));
+        let (width, height) = cropped.dimensions();
+        assert!((200..=210).contains(&width), "{}", width);
+        assert!((300..=310).contains(&height), "{}", height);
+    }
+
+    #[test]
+    fn skew_detection() {
+        let image = receipt(400, 400);
+        let paper = image::imageops::crop_imm(&image, 100, 80, 200, 240).to_image();
+        let skewed = rotate(&paper, 3.);
+        let angle = skew(&DynamicImage::ImageRgb8(skewed).to_luma8());
+        assert!((angle - 3.).abs() < 0.3, "{}", angle);
+    }
+}
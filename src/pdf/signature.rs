@@ -0,0 +1,418 @@
+//! Digital signatures of the combined document.
+//!
+//! The signature field is prepared on the document with placeholders for
+//! the signed byte range and the signature value, both of which are filled
+//! in on the serialized document, see [`sign`].
+
+use super::info::pdf_date;
+use super::overlay::form_xobject;
+use super::page::add_annotation;
+use super::strings::encode_text_string;
+use super::tabular::{text, Alignment};
+use super::types::*;
+use crate::errors::*;
+
+use lopdf::{dictionary, Document, Object, StringFormat};
+use openssl::cms::{CMSOptions, CmsContentInfo};
+use openssl::nid::Nid;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::x509::X509;
+
+use fs_err as fs;
+use std::path::Path;
+
+/// Bytes reserved for the DER encoded signature.
+const SIGNATURE_SIZE: usize = 16384;
+
+/// Stands in for the offsets of the signed byte range until they are known.
+const BYTE_RANGE_PLACEHOLDER: i64 = 9_999_999_999;
+
+/// Private key and certificates used to sign.
+pub struct Signer {
+    key: PKey<Private>,
+    certificate: X509,
+    chain: Stack<X509>,
+}
+
+impl Signer {
+    /// Load key and certificates from a PKCS#12 file.
+    pub fn from_pkcs12(path: &Path, password: &str) -> Result<Self> {
+        let der = fs::read(path)?;
+        let parsed = Pkcs12::from_der(&der)?
+            .parse2(password)
+            .wrap_err_with(|| eyre!("Failed to decrypt {}", path.display()))?;
+        let key = parsed
+            .pkey
+            .ok_or_else(|| eyre!("{} contains no private key", path.display()))?;
+        let certificate = parsed
+            .cert
+            .ok_or_else(|| eyre!("{} contains no certificate", path.display()))?;
+        let chain = match parsed.ca {
+            Some(chain) => chain,
+            None => Stack::new()?,
+        };
+        Ok(Self {
+            key,
+            certificate,
+            chain,
+        })
+    }
+
+    /// Load the certificate, optionally followed by its chain, and the
+    /// private key from PEM files.
+    pub fn from_pem(certificate: &Path, key: &Path, password: Option<&str>) -> Result<Self> {
+        let mut certificates = X509::stack_from_pem(&fs::read(certificate)?)?.into_iter();
+        let first = certificates
+            .next()
+            .ok_or_else(|| eyre!("{} contains no certificate", certificate.display()))?;
+        let mut chain = Stack::new()?;
+        for certificate in certificates {
+            chain.push(certificate)?;
+        }
+        let pem = fs::read(key)?;
+        let key = match password {
+            Some(password) => PKey::private_key_from_pem_passphrase(&pem, password.as_bytes()),
+            None => PKey::private_key_from_pem(&pem),
+        }
+        .wrap_err_with(|| eyre!("Failed to load private key {}", key.display()))?;
+        Ok(Self {
+            key,
+            certificate: first,
+            chain,
+        })
+    }
+
+    /// Common name of the certificate subject.
+    pub fn name(&self) -> Option<String> {
+        self.certificate
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| entry.data().as_utf8().ok())
+            .map(|name| name.to_string())
+    }
+}
+
+/// Details recorded in the signature dictionary and shown in the signature box.
+#[derive(Debug, Clone, Default)]
+pub struct SignatureInfo {
+    pub name: String,
+    pub reason: Option<String>,
+    pub location: Option<String>,
+    pub contact: Option<String>,
+}
+
+/// Add a signature field with a visible box covering `rect` to the page with
+/// zero based index `page`, the signature itself is added by [`sign`].
+pub fn prepare_signature(
+    document: &mut Document,
+    page: usize,
+    rect: Rect,
+    info: &SignatureInfo,
+) -> Result<()> {
+    let page_id = *document
+        .get_pages()
+        .values()
+        .nth(page)
+        .ok_or_else(|| eyre!("Can not sign page {}, out of bounds", page + 1))?;
+    let now = chrono::Local::now();
+
+    let mut signature = dictionary! {
+        "Type" => "Sig",
+        "Filter" => "Adobe.PPKLite",
+        "SubFilter" => "adbe.pkcs7.detached",
+        "ByteRange" => vec![
+            0.into(),
+            BYTE_RANGE_PLACEHOLDER.into(),
+            BYTE_RANGE_PLACEHOLDER.into(),
+            BYTE_RANGE_PLACEHOLDER.into(),
+        ],
+        "Contents" => Object::String(vec![0u8; SIGNATURE_SIZE], StringFormat::Hexadecimal),
+        "M" => encode_text_string(&pdf_date(now)),
+        "Name" => encode_text_string(&info.name),
+    };
+    if let Some(ref reason) = info.reason {
+        signature.set("Reason", encode_text_string(reason));
+    }
+    if let Some(ref location) = info.location {
+        signature.set("Location", encode_text_string(location));
+    }
+    if let Some(ref contact) = info.contact {
+        signature.set("ContactInfo", encode_text_string(contact));
+    }
+    let signature = document.add_object(signature);
+
+    let width = rect.upper_right.x - rect.lower_left.x;
+    let height = rect.upper_right.y - rect.lower_left.y;
+    let appearance = form_xobject(document, width, height, |canvas| {
+        let border = Rect::from_corners(
+            Point {
+                x: Pt(0.5),
+                y: Pt(0.5),
+            },
+            Point {
+                x: width - Pt(0.5),
+                y: height - Pt(0.5),
+            },
+        );
+        canvas.layer.add_shape(printpdf::Line {
+            points: vec![
+                (border.lower_left, false),
+                (
+                    Point {
+                        x: border.upper_right.x,
+                        y: border.lower_left.y,
+                    },
+                    false,
+                ),
+                (border.upper_right, false),
+                (
+                    Point {
+                        x: border.lower_left.x,
+                        y: border.upper_right.y,
+                    },
+                    false,
+                ),
+            ],
+            is_closed: true,
+            has_fill: false,
+            has_stroke: true,
+            is_clipping_path: false,
+        });
+
+        let lines = std::iter::once(format!("Digitally signed by {}", info.name))
+            .chain(std::iter::once(format!(
+                "Date: {}",
+                now.format("%Y-%m-%d %H:%M:%S %:z")
+            )))
+            .chain(
+                info.reason
+                    .iter()
+                    .map(|reason| format!("Reason: {}", reason)),
+            )
+            .chain(
+                info.location
+                    .iter()
+                    .map(|location| format!("Location: {}", location)),
+            )
+            .collect::<Vec<_>>();
+        // shrink the text to fit small boxes, lines which still do not fit are dropped
+        let padding = 3.;
+        let size = (5..=8)
+            .rev()
+            .find(|size| padding * 2. + lines.len() as f64 * *size as f64 * 1.3 <= height.0)
+            .unwrap_or(5);
+        let mut anchor = Point {
+            x: Pt(padding * 2.),
+            y: height - Pt(padding + size as f64),
+        };
+        for line in lines {
+            if anchor.y < Pt(padding) {
+                break;
+            }
+            text(
+                &canvas.layer,
+                anchor,
+                &line,
+                &canvas.font,
+                size,
+                Alignment::Left,
+            )?;
+            anchor.y -= Pt(size as f64 * 1.3);
+        }
+        Ok(())
+    })?;
+
+    let field = document.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "FT" => "Sig",
+        "T" => encode_text_string("Signature"),
+        "Rect" => rect.to_pdf_array(),
+        // printed and locked
+        "F" => 4 | 128,
+        "P" => page_id,
+        "V" => signature,
+        "AP" => dictionary! { "N" => appearance },
+    });
+    add_annotation(document, page_id, field)?;
+
+    let catalog_id = document
+        .trailer
+        .get(b"Root")
+        .and_then(Object::as_reference)
+        .map_err(|e| eyre!("Document has no catalog: {:?}", e))?;
+    let catalog = document
+        .get_object_mut(catalog_id)
+        .and_then(Object::as_dict_mut)
+        .map_err(|e| eyre!("Catalog is not a dictionary: {:?}", e))?;
+    catalog.set(
+        "AcroForm",
+        dictionary! {
+            "Fields" => vec![Object::Reference(field)],
+            // signatures exist, only append
+            "SigFlags" => 3,
+        },
+    );
+    Ok(())
+}
+
+/// Sign the serialized document `bytes`, which must contain a signature
+/// field prepared with [`prepare_signature`].
+///
+/// The byte range is filled in and a detached CMS signature over everything
+/// but the signature value itself is written into the reserved space.
+pub fn sign(bytes: &mut [u8], signer: &Signer) -> Result<()> {
+    let placeholder = format!("[0 {p} {p} {p}]", p = BYTE_RANGE_PLACEHOLDER);
+    let range_start = find(bytes, placeholder.as_bytes())
+        .ok_or_else(|| eyre!("Document has no prepared signature byte range"))?;
+    let zeros = vec![b'0'; SIGNATURE_SIZE * 2];
+    let contents_start = find(bytes, &zeros)
+        .filter(|start| *start > 0 && bytes[start - 1] == b'<')
+        .ok_or_else(|| eyre!("Document has no space reserved for the signature"))?
+        - 1;
+    // including the angle brackets
+    let contents_end = contents_start + zeros.len() + 2;
+
+    let byte_range = format!(
+        "[0 {} {} {}]",
+        contents_start,
+        contents_end,
+        bytes.len() - contents_end
+    );
+    let byte_range = format!("{:width$}", byte_range, width = placeholder.len());
+    bytes[range_start..range_start + placeholder.len()].copy_from_slice(byte_range.as_bytes());
+
+    let mut signed = bytes[..contents_start].to_vec();
+    signed.extend_from_slice(&bytes[contents_end..]);
+    let cms = CmsContentInfo::sign(
+        Some(&signer.certificate),
+        Some(&signer.key),
+        Some(&signer.chain),
+        Some(&signed),
+        CMSOptions::DETACHED | CMSOptions::BINARY,
+    )?
+    .to_der()?;
+    if cms.len() > SIGNATURE_SIZE {
+        bail!(
+            "Signature of {} bytes exceeds the reserved {} bytes",
+            cms.len(),
+            SIGNATURE_SIZE
+        );
+    }
+    let hex = cms.iter().map(|b| format!("{:02X}", b)).collect::<String>();
+    bytes[contents_start + 1..contents_start + 1 + hex.len()].copy_from_slice(hex.as_bytes());
+    Ok(())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::constants::DIN_A4;
+    use super::super::helper::signature_area;
+    use super::super::textual::{text_receipt, TextKind};
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::rsa::Rsa;
+    use openssl::x509::store::X509StoreBuilder;
+    use openssl::x509::X509NameBuilder;
+
+    /// A throwaway self-signed signer.
+    fn signer() -> Signer {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "Alice").unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        Signer {
+            key,
+            certificate: builder.build(),
+            chain: Stack::new().unwrap(),
+        }
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let signer = signer();
+        assert_eq!(signer.name().as_deref(), Some("Alice"));
+
+        let mut document = text_receipt(b"Total 5,00 EUR", TextKind::Plain, DIN_A4).unwrap();
+        let info = SignatureInfo {
+            name: "Alice".to_owned(),
+            reason: Some("Reimbursement request".to_owned()),
+            ..Default::default()
+        };
+        prepare_signature(&mut document, 0, signature_area(), &info).unwrap();
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+        sign(&mut bytes, &signer).unwrap();
+
+        // the byte range covers everything but the hex string of the signature
+        let start = find(&bytes, b"/ByteRange").unwrap();
+        let open = start + find(&bytes[start..], b"[").unwrap();
+        let close = open + find(&bytes[open..], b"]").unwrap();
+        let range = std::str::from_utf8(&bytes[open + 1..close])
+            .unwrap()
+            .split_whitespace()
+            .map(|n| n.parse::<usize>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(range.len(), 4);
+        assert_eq!(range[0], 0);
+        assert_eq!(bytes[range[1]], b'<');
+        assert_eq!(bytes[range[2] - 1], b'>');
+        assert_eq!(range[2] - range[1], SIGNATURE_SIZE * 2 + 2);
+        assert_eq!(range[2] + range[3], bytes.len());
+
+        let hex = std::str::from_utf8(&bytes[range[1] + 1..range[2] - 1]).unwrap();
+        let der = (0..hex.len())
+            .step_by(2)
+            .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).unwrap())
+            .collect::<Vec<_>>();
+        let mut cms = CmsContentInfo::from_der(&der).unwrap();
+
+        let mut signed = bytes[..range[1]].to_vec();
+        signed.extend_from_slice(&bytes[range[2]..]);
+        let mut store = X509StoreBuilder::new().unwrap();
+        store.add_cert(signer.certificate.clone()).unwrap();
+        let store = store.build();
+        cms.verify(
+            None,
+            Some(&store),
+            Some(&signed),
+            None,
+            CMSOptions::DETACHED | CMSOptions::BINARY,
+        )
+        .unwrap();
+
+        // any change to the signed bytes breaks the signature
+        signed[10] ^= 1;
+        assert!(cms
+            .verify(
+                None,
+                Some(&store),
+                Some(&signed),
+                None,
+                CMSOptions::DETACHED | CMSOptions::BINARY,
+            )
+            .is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Sign-off block of the summary page, with one column for the employee
+//! followed by one for each approver.
+
+use super::constants::{DIN_A4, TTF_REGULAR};
+use super::tabular::{text, text_width, Alignment};
+use super::types::*;
+use crate::errors::*;
+
+use printpdf::{Line, PdfLayerReference};
+use serde::Deserialize;
+
+/// Baseline of the column titles, just below the bank details.
+const TOP: f64 = 112.;
+const SIZE: i32 = 7;
+const TITLE_SIZE: i32 = 9;
+const COLUMN_GAP: f64 = 20.;
+
+/// A line to fill in within each column of the sign-off block.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SignOffField {
+    Name,
+    Date,
+    Signature,
+}
+
+impl SignOffField {
+    /// Vertical space taken by the field, signatures need some room.
+    fn height(self) -> Pt {
+        match self {
+            SignOffField::Signature => Pt(30.),
+            _ => Pt(15.),
+        }
+    }
+}
+
+/// One party signing off the request.
+#[derive(Debug, Clone)]
+pub struct SignOffColumn {
+    pub title: String,
+    /// Pre-filled into the name field.
+    pub name: Option<String>,
+}
+
+/// Content of the sign-off block.
+#[derive(Debug, Clone)]
+pub struct SignOff {
+    /// The employee first, followed by the approvers.
+    pub columns: Vec<SignOffColumn>,
+    /// Fields of each column with their labels, top to bottom.
+    pub fields: Vec<(SignOffField, String)>,
+}
+
+impl SignOff {
+    /// Horizontal extent of each column.
+    fn columns_x(&self) -> Vec<(Pt, Pt)> {
+        let left = Pt::from(DIN_A4.width) * 0.10;
+        let right = Pt::from(DIN_A4.width) * 0.90;
+        let n = self.columns.len().max(1) as f64;
+        let width = (right - left - Pt(COLUMN_GAP) * (n - 1.)) / n;
+        (0..self.columns.len())
+            .map(|idx| {
+                let x = left + (width + Pt(COLUMN_GAP)) * idx as f64;
+                (x, x + width)
+            })
+            .collect()
+    }
+
+    /// Where the lines of the fields start, right of the widest label.
+    fn label_width(&self) -> Result<Pt> {
+        let mut width = Pt(0.);
+        for (_, label) in self.fields.iter() {
+            let label_width = text_width(label, TTF_REGULAR, SIZE)?;
+            if label_width > width {
+                width = label_width;
+            }
+        }
+        Ok(width + Pt(4.))
+    }
+
+    /// Line and the area above it of each field, for the column spanning `x`.
+    fn field_areas(&self, (left, right): (Pt, Pt)) -> Result<Vec<Rect>> {
+        let start = left + self.label_width()?;
+        let mut y = Pt(TOP);
+        Ok(self
+            .fields
+            .iter()
+            .map(|(field, _)| {
+                let top = y - Pt(4.);
+                y -= field.height();
+                Rect::from_corners(Point { x: start, y }, Point { x: right, y: top })
+            })
+            .collect())
+    }
+
+    /// Area of the employee signature field, if the block has one.
+    pub fn signature_area(&self) -> Result<Option<Rect>> {
+        let column = match self.columns_x().first() {
+            Some(column) => *column,
+            None => return Ok(None),
+        };
+        let areas = self.field_areas(column)?;
+        Ok(self
+            .fields
+            .iter()
+            .zip(areas)
+            .find(|((field, _), _)| *field == SignOffField::Signature)
+            .map(|(_, area)| area))
+    }
+
+    /// Render the sign-off block below the bank details.
+    pub fn render(
+        &self,
+        layer: &PdfLayerReference,
+        font: &IndirectFontRef,
+        bold: &IndirectFontRef,
+        line_color: Color,
+    ) -> Result<()> {
+        for (column, x) in self.columns.iter().zip(self.columns_x()) {
+            text(
+                layer,
+                Point { x: x.0, y: Pt(TOP) },
+                &column.title,
+                bold,
+                TITLE_SIZE,
+                Alignment::Left,
+            )?;
+
+            let areas = self.field_areas(x)?;
+            for ((field, label), area) in self.fields.iter().zip(areas) {
+                let y = area.lower_left.y;
+                text(
+                    layer,
+                    Point { x: x.0, y },
+                    label,
+                    font,
+                    SIZE,
+                    Alignment::Left,
+                )?;
+
+                layer.save_graphics_state();
+                layer.set_outline_color(line_color.clone());
+                layer.add_shape(Line {
+                    points: vec![
+                        (
+                            Point {
+                                x: area.lower_left.x,
+                                y: y - Pt(2.),
+                            },
+                            false,
+                        ),
+                        (
+                            Point {
+                                x: area.upper_right.x,
+                                y: y - Pt(2.),
+                            },
+                            false,
+                        ),
+                    ],
+                    is_closed: false,
+                    has_fill: false,
+                    has_stroke: true,
+                    is_clipping_path: false,
+                });
+                layer.restore_graphics_state();
+
+                if let (SignOffField::Name, Some(name)) = (field, &column.name) {
+                    text(
+                        layer,
+                        Point {
+                            x: area.lower_left.x + Pt(2.),
+                            y,
+                        },
+                        name,
+                        bold,
+                        SIZE,
+                        Alignment::Left,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+//! Stamps which tie receipt pages to their row in the summary table.
+
+use super::constants::TTF_REGULAR;
+use super::overlay::Canvas;
+use super::tabular::{text, text_width, Alignment};
+use super::types::*;
+use crate::errors::*;
+
+use printpdf::{Greyscale, Line};
+use serde::Deserialize;
+
+/// Stamp `label` into the upper right corner of the page.
+///
+/// The label is put onto a white box, so it stays readable
+/// on top of scanned receipts.
+pub fn receipt_stamp(canvas: &mut Canvas, label: &str) -> Result<()> {
+    let size = 8;
+    let padding = Pt(3.);
+    let width = text_width(label, TTF_REGULAR, size)?;
+    let anchor = Point {
+        x: canvas.width - Pt(12.),
+        y: canvas.height - Pt(18.),
+    };
+
+    let left = anchor.x - width - padding;
+    let right = anchor.x + padding;
+    let bottom = anchor.y - padding;
+    let top = anchor.y + Pt(size as f64) + padding;
+    let line = Line {
+        points: vec![
+            (Point { x: left, y: top }, false),
+            (Point { x: left, y: bottom }, false),
+            (
+                Point {
+                    x: right,
+                    y: bottom,
+                },
+                false,
+            ),
+            (Point { x: right, y: top }, false),
+        ],
+        is_closed: true,
+        has_fill: true,
+        has_stroke: false,
+        is_clipping_path: false,
+    };
+
+    canvas.layer.save_graphics_state();
+    canvas.layer.set_fill_color(Color::Greyscale(Greyscale {
+        percent: 1.0,
+        icc_profile: None,
+    }));
+    canvas.layer.add_shape(line);
+    canvas.layer.restore_graphics_state();
+
+    text(
+        &canvas.layer,
+        anchor,
+        label,
+        &canvas.font,
+        size,
+        Alignment::Right,
+    )?;
+    Ok(())
+}
+
+/// Where on the page the footer is placed.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum FooterPosition {
+    TopLeft,
+    TopCenter,
+    TopRight,
+    BottomLeft,
+    BottomCenter,
+    #[default]
+    BottomRight,
+}
+
+/// Stamp the single line `label` onto the page at `position`.
+pub fn page_footer(
+    canvas: &mut Canvas,
+    label: &str,
+    position: FooterPosition,
+    size: i32,
+) -> Result<()> {
+    let margin = Pt(12.);
+    let top = canvas.height - margin - Pt(size as f64);
+    let (x, y, alignment) = match position {
+        FooterPosition::TopLeft => (margin, top, Alignment::Left),
+        FooterPosition::TopCenter => (canvas.width * 0.5, top, Alignment::Center),
+        FooterPosition::TopRight => (canvas.width - margin, top, Alignment::Right),
+        FooterPosition::BottomLeft => (margin, margin, Alignment::Left),
+        FooterPosition::BottomCenter => (canvas.width * 0.5, margin, Alignment::Center),
+        FooterPosition::BottomRight => (canvas.width - margin, margin, Alignment::Right),
+    };
+    text(
+        &canvas.layer,
+        Point { x, y },
+        label,
+        &canvas.font,
+        size,
+        alignment,
+    )?;
+    Ok(())
+}
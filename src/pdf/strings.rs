@@ -0,0 +1,36 @@
+//! Conversion between rust strings and pdf text strings.
+//!
+//! Text strings (titles, annotations, metadata) are either encoded
+//! with `PDFDocEncoding` or as `UTF-16BE` prefixed with a byte order mark.
+
+use lopdf::{Object, StringFormat};
+
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// Encode a text string, plain ascii is kept as is, everything else
+/// is stored as `UTF-16BE`.
+pub fn encode_text_string(s: &str) -> Object {
+    if s.is_ascii() {
+        return Object::string_literal(s);
+    }
+    let bytes = UTF16BE_BOM
+        .iter()
+        .copied()
+        .chain(s.encode_utf16().flat_map(|unit| unit.to_be_bytes()))
+        .collect::<Vec<u8>>();
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+/// Decode a text string, falls back to latin1 for non `UTF-16BE` strings,
+/// which is close enough to `PDFDocEncoding` for display purposes.
+pub fn decode_text_string(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(UTF16BE_BOM) {
+        let units = utf16
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect::<Vec<u16>>();
+        String::from_utf16_lossy(&units)
+    } else {
+        bytes.iter().map(|&b| b as char).collect()
+    }
+}
@@ -3,6 +3,7 @@
 //! Assumes your values implements `trait ToString`.
 
 use super::super::constants::TTF_REGULAR;
+use super::super::types::Rect;
 use super::text::text_width;
 use super::ColumnWidthSet;
 use super::{Alignment, RenderState, RenderStyle, RenderStyleSet};
@@ -61,7 +62,12 @@ where
         }
     }
 
-    pub fn render(mut self, styleset: &RenderStyleSet, columnwidths: ColumnWidthSet) -> Result<()> {
+    /// Render the table, returns the area covered by each content row.
+    pub fn render(
+        mut self,
+        styleset: &RenderStyleSet,
+        columnwidths: ColumnWidthSet,
+    ) -> Result<Vec<Rect>> {
         let mut hbounds = Vec::with_capacity(columnwidths.len() + 1);
         hbounds.insert(0, self.anchor.x);
         assert_eq!(hbounds.len(), 1);
@@ -81,7 +87,19 @@ where
         self.header(&mut state, &styleset.header)?;
 
         // draw content
+        let mut rows = Vec::new();
         for row in self.content.clone() {
+            let (top, bottom) = state.current_row_y_range();
+            rows.push(Rect {
+                lower_left: Point {
+                    x: *state.hbounds.first().unwrap(),
+                    y: bottom,
+                },
+                upper_right: Point {
+                    x: *state.hbounds.last().unwrap(),
+                    y: top,
+                },
+            });
             self.render_row(row, &mut state, &styleset.data)?;
             state.advance_to_next_row();
         }
@@ -113,7 +131,7 @@ where
             self.hline(&state)?;
         }
 
-        Ok(())
+        Ok(rows)
     }
 
     fn render_row(&mut self, row: I0, state: &mut RenderState, style: &RenderStyle) -> Result<()> {
@@ -0,0 +1,635 @@
+//! Receipts given as text, i.e. plain text, e-mails or simple html order
+//! confirmations, rendered to pages.
+//!
+//! Plain text keeps its layout in a monospace font, html is reduced to
+//! paragraphs which are wrapped to the width of the page.
+
+use super::constants::{Dimensions, TTF_BOLD, TTF_MONO, TTF_REGULAR};
+use super::helper::flush_pdf_ops;
+use super::tabular::text_width;
+use super::types::*;
+use crate::errors::*;
+
+use lopdf::Document;
+use printpdf::PdfDocument;
+
+/// Margin around the rendered text.
+const MARGIN: Mm = Mm(20.);
+
+/// Font size of html and e-mail headers.
+const FONT_SIZE: i32 = 10;
+
+/// Font size of plain text.
+const MONO_FONT_SIZE: i32 = 9;
+
+/// Headers of e-mails shown above the body.
+const EMAIL_HEADERS: &[&str] = &["From", "To", "Date", "Subject"];
+
+/// The formats of text receipts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextKind {
+    Plain,
+    Email,
+    Html,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Mono,
+    Regular,
+    Bold,
+}
+
+/// A paragraph, wrapped to the width of the page when rendered.
+#[derive(Debug, Clone, PartialEq)]
+struct Paragraph {
+    style: Style,
+    text: String,
+}
+
+impl Paragraph {
+    fn new(style: Style, text: impl Into<String>) -> Self {
+        Self {
+            style,
+            text: text.into(),
+        }
+    }
+}
+
+/// Render the text receipt `bytes` onto as many pages of `dimensions` as needed.
+pub fn text_receipt(bytes: &[u8], kind: TextKind, dimensions: Dimensions) -> Result<Document> {
+    let paragraphs = match kind {
+        TextKind::Plain => plain(&decode_charset(bytes, None)),
+        TextKind::Email => email(bytes)?,
+        TextKind::Html => html(&decode_charset(bytes, None)),
+    };
+    render(&paragraphs, dimensions)
+}
+
+fn render(paragraphs: &[Paragraph], dimensions: Dimensions) -> Result<Document> {
+    let (doc, page1, layer1) =
+        PdfDocument::new("Receipt", dimensions.width, dimensions.height, "Layer 1");
+    let mono = doc.add_external_font(TTF_MONO)?;
+    let regular = doc.add_external_font(TTF_REGULAR)?;
+    let bold = doc.add_external_font(TTF_BOLD)?;
+
+    let margin = Pt::from(MARGIN);
+    let width = Pt::from(dimensions.width) - margin * 2.;
+    let top = Pt::from(dimensions.height) - margin;
+    let mut layer = doc.get_page(page1).get_layer(layer1);
+    let mut y = top;
+    for paragraph in paragraphs {
+        let (bytes, font, size) = match paragraph.style {
+            Style::Mono => (TTF_MONO, &mono, MONO_FONT_SIZE),
+            Style::Regular => (TTF_REGULAR, &regular, FONT_SIZE),
+            Style::Bold => (TTF_BOLD, &bold, FONT_SIZE),
+        };
+        let line_height = Pt(size as f64 * 1.35);
+        for line in wrap(&paragraph.text, bytes, size, width)? {
+            if y - line_height < margin {
+                let (page, page_layer) =
+                    doc.add_page(dimensions.width, dimensions.height, "Layer 1");
+                layer = doc.get_page(page).get_layer(page_layer);
+                y = top;
+            }
+            y -= line_height;
+            if !line.is_empty() {
+                layer.use_text(line, size as f64, Mm::from(margin), Mm::from(y), font);
+            }
+        }
+        // paragraphs are set apart, lines of plain text are not
+        if paragraph.style != Style::Mono {
+            y -= line_height * 0.4;
+        }
+    }
+    flush_pdf_ops(doc)
+}
+
+/// Break `text` into lines no wider than `width`, at spaces where possible.
+pub(super) fn wrap(text: &str, font: &[u8], size: i32, width: Pt) -> Result<Vec<String>> {
+    // shaping empty text is undefined behaviour in harfbuzz
+    let measure = |text: &str| -> Result<Pt> {
+        if text.is_empty() {
+            Ok(Pt(0.))
+        } else {
+            text_width(text, font, size)
+        }
+    };
+    let mut lines = Vec::new();
+    let mut line: Option<String> = None;
+    // splitting at single spaces keeps the indentation of plain text
+    for word in text.split(' ') {
+        let candidate = match line {
+            Some(ref line) => format!("{} {}", line, word),
+            None => word.to_owned(),
+        };
+        if line.is_some() && measure(&candidate)? > width {
+            lines.extend(line.take());
+            line = Some(word.to_owned());
+        } else {
+            line = Some(candidate);
+        }
+        // words longer than a line are broken up anywhere
+        while let Some(current) = line.take() {
+            if measure(&current)? <= width {
+                line = Some(current);
+                break;
+            }
+            let mut split = current
+                .char_indices()
+                .nth(1)
+                .map_or(current.len(), |(idx, _)| idx);
+            for (idx, _) in current.char_indices().skip(2) {
+                if measure(&current[..idx])? > width {
+                    break;
+                }
+                split = idx;
+            }
+            lines.push(current[..split].to_owned());
+            line = Some(current[split..].to_owned());
+        }
+    }
+    lines.extend(line);
+    Ok(lines)
+}
+
+fn plain(text: &str) -> Vec<Paragraph> {
+    text.lines()
+        .map(|line| Paragraph::new(Style::Mono, line.replace('\t', "    ").trim_end()))
+        .collect()
+}
+
+/// Characters of windows-1252 in the range `0x80..=0x9F`, in which it
+/// differs from iso-8859-1. Unassigned bytes are kept as control characters.
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// Decode `bytes` of the given `charset`, utf-8 unless it is a latin one.
+fn decode_charset(bytes: &[u8], charset: Option<&str>) -> String {
+    let charset = charset.unwrap_or("utf-8").to_lowercase();
+    match charset.as_str() {
+        "iso-8859-1" | "latin1" | "us-ascii" => bytes.iter().map(|byte| *byte as char).collect(),
+        "windows-1252" | "cp1252" => bytes
+            .iter()
+            .map(|byte| match byte {
+                0x80..=0x9F => WINDOWS_1252[(byte - 0x80) as usize],
+                _ => *byte as char,
+            })
+            .collect(),
+        "iso-8859-15" | "latin9" => bytes
+            .iter()
+            .map(|byte| match byte {
+                0xA4 => '€',
+                0xA6 => 'Š',
+                0xA8 => 'š',
+                0xB4 => 'Ž',
+                0xB8 => 'ž',
+                0xBC => 'Œ',
+                0xBD => 'œ',
+                0xBE => 'Ÿ',
+                _ => *byte as char,
+            })
+            .collect(),
+        _ => String::from_utf8_lossy(bytes)
+            .trim_start_matches('\u{feff}')
+            .to_owned(),
+    }
+}
+
+/// Split a message into its header block and its body.
+fn split_message(message: &[u8]) -> (&[u8], &[u8]) {
+    let crlf = message.windows(4).position(|w| w == b"\r\n\r\n");
+    let lf = message.windows(2).position(|w| w == b"\n\n");
+    match (crlf, lf) {
+        (Some(crlf), Some(lf)) if lf < crlf => (&message[..lf], &message[lf + 2..]),
+        (Some(crlf), _) => (&message[..crlf], &message[crlf + 4..]),
+        (None, Some(lf)) => (&message[..lf], &message[lf + 2..]),
+        (None, None) => (message, &[]),
+    }
+}
+
+/// Parse the header block into names and unfolded values.
+fn headers(block: &[u8]) -> Vec<(String, String)> {
+    let mut headers = Vec::<(String, String)>::new();
+    for line in String::from_utf8_lossy(block).lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+    }
+    headers
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// The lower case mime type and the parameters of a `Content-Type` value.
+fn content_type(value: &str) -> (String, Vec<(String, String)>) {
+    let mut parts = value.split(';');
+    let mime = parts.next().unwrap_or_default().trim().to_lowercase();
+    let parameters = parts
+        .filter_map(|parameter| parameter.split_once('='))
+        .map(|(key, value)| {
+            (
+                key.trim().to_lowercase(),
+                value.trim().trim_matches('"').to_owned(),
+            )
+        })
+        .collect();
+    (mime, parameters)
+}
+
+fn email(message: &[u8]) -> Result<Vec<Paragraph>> {
+    let (block, body) = split_message(message);
+    let headers = headers(block);
+    let mut paragraphs = EMAIL_HEADERS
+        .iter()
+        .filter_map(|name| {
+            header(&headers, name).map(|value| {
+                let style = if *name == "Subject" {
+                    Style::Bold
+                } else {
+                    Style::Regular
+                };
+                Paragraph::new(style, format!("{}: {}", name, decode_words(value)))
+            })
+        })
+        .collect::<Vec<_>>();
+    let (html_body, text) = text_part(&headers, body)
+        .ok_or_else(|| eyre!("E-mail receipt has no text or html part"))?;
+    paragraphs.push(Paragraph::new(Style::Regular, ""));
+    paragraphs.extend(if html_body { html(&text) } else { plain(&text) });
+    Ok(paragraphs)
+}
+
+/// The decoded text of the message part, along with whether it is html.
+/// Plain text is preferred over html for multipart messages.
+fn text_part(headers: &[(String, String)], body: &[u8]) -> Option<(bool, String)> {
+    let (mime, parameters) = content_type(header(headers, "Content-Type").unwrap_or("text/plain"));
+    let parameter = |name: &str| {
+        parameters
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    if mime.starts_with("multipart/") {
+        let boundary = format!("--{}", parameter("boundary")?);
+        let text = String::from_utf8_lossy(body);
+        let parts = text
+            .split(boundary.as_str())
+            .skip(1)
+            // the closing delimiter is followed by `--`
+            .filter(|part| !part.starts_with("--"))
+            .filter_map(|part| {
+                let part = part.trim_start_matches(['\r', '\n']);
+                let (block, body) = split_message(part.as_bytes());
+                text_part(&self::headers(block), body)
+            })
+            .collect::<Vec<_>>();
+        let plain = parts.iter().position(|(html, _)| !html);
+        return parts.into_iter().nth(plain.unwrap_or(0));
+    }
+    let html = match mime.as_str() {
+        "text/plain" => false,
+        "text/html" => true,
+        _ => return None,
+    };
+    let encoding = header(headers, "Content-Transfer-Encoding")
+        .unwrap_or_default()
+        .to_lowercase();
+    let decoded = match encoding.as_str() {
+        "base64" => base64::decode(
+            body.iter()
+                .copied()
+                .filter(|byte| !byte.is_ascii_whitespace())
+                .collect::<Vec<u8>>(),
+        )
+        .ok()?,
+        "quoted-printable" => quoted_printable(body, false),
+        _ => body.to_vec(),
+    };
+    Some((html, decode_charset(&decoded, parameter("charset"))))
+}
+
+/// Decode quoted printable data, `underscores` are spaces in encoded words.
+fn quoted_printable(data: &[u8], underscores: bool) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut idx = 0;
+    while idx < data.len() {
+        match data[idx] {
+            b'=' => {
+                let rest = &data[idx + 1..];
+                if rest.starts_with(b"\r\n") {
+                    idx += 3;
+                } else if rest.starts_with(b"\n") {
+                    idx += 2;
+                } else if let Some(byte) = rest
+                    .get(..2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    decoded.push(byte);
+                    idx += 3;
+                } else {
+                    decoded.push(b'=');
+                    idx += 1;
+                }
+            }
+            b'_' if underscores => {
+                decoded.push(b' ');
+                idx += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                idx += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Decode the encoded words of a header value, i.e. `=?utf-8?Q?Bestellbest=C3=A4tigung?=`.
+fn decode_words(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let word = rest[start + 2..].splitn(3, '?').collect::<Vec<_>>();
+        let end = word
+            .get(2)
+            .and_then(|text| text.find("?="))
+            .filter(|_| word.len() == 3);
+        let (charset, encoding, text) = match end {
+            Some(end) => (word[0], word[1], &word[2][..end]),
+            None => break,
+        };
+        let bytes = match encoding {
+            "B" | "b" => base64::decode(text).ok(),
+            "Q" | "q" => Some(quoted_printable(text.as_bytes(), true)),
+            _ => None,
+        };
+        let bytes = if let Some(bytes) = bytes {
+            bytes
+        } else {
+            break;
+        };
+        // whitespace between adjacent encoded words is dropped
+        let between = &rest[..start];
+        if !(after_word && between.trim().is_empty()) {
+            decoded.push_str(between);
+        }
+        decoded.push_str(&decode_charset(&bytes, Some(charset)));
+        let consumed = start + 2 + charset.len() + encoding.len() + text.len() + 4;
+        rest = &rest[consumed..];
+        after_word = true;
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Reduce html to paragraphs, headings are set in bold.
+fn html(source: &str) -> Vec<Paragraph> {
+    let mut paragraphs = Vec::new();
+    let mut current = String::new();
+    let mut heading = false;
+    // content of these elements is not displayed
+    let mut hidden: Option<String> = None;
+
+    let flush = |paragraphs: &mut Vec<Paragraph>, current: &mut String, heading: bool| {
+        let text = current.trim();
+        if !text.is_empty() {
+            let style = if heading { Style::Bold } else { Style::Regular };
+            paragraphs.push(Paragraph::new(style, text));
+        }
+        current.clear();
+    };
+
+    let mut rest = source;
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        if rest.starts_with('<') {
+            // an unterminated tag swallows the rest
+            let (tag, remainder) = rest[1..].split_once('>').unwrap_or((&rest[1..], ""));
+            rest = remainder;
+            let closing = tag.starts_with('/');
+            let name = tag
+                .trim_start_matches('/')
+                .split(|c: char| c.is_ascii_whitespace() || c == '/')
+                .next()
+                .unwrap_or_default()
+                .to_lowercase();
+            if let Some(ref element) = hidden {
+                if closing && *element == name {
+                    hidden = None;
+                }
+                continue;
+            }
+            match name.as_str() {
+                "script" | "style" | "head" | "title" if !closing => hidden = Some(name.clone()),
+                "br" => flush(&mut paragraphs, &mut current, heading),
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                    flush(&mut paragraphs, &mut current, heading);
+                    heading = !closing;
+                }
+                "p" | "div" | "tr" | "table" | "ul" | "ol" | "section" | "article" | "header"
+                | "footer" | "blockquote" | "hr" | "dl" | "dt" | "dd" => {
+                    flush(&mut paragraphs, &mut current, heading);
+                }
+                "li" => {
+                    flush(&mut paragraphs, &mut current, heading);
+                    if !closing {
+                        current.push_str("• ");
+                    }
+                }
+                // table cells are kept on one line
+                "td" | "th" if !closing && !current.trim().is_empty() => current.push_str("   "),
+                _ => {}
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            if hidden.is_none() {
+                for c in decode_entities(&rest[..end]).chars() {
+                    if c.is_ascii_whitespace() {
+                        if !current.is_empty() && !current.ends_with(' ') {
+                            current.push(' ');
+                        }
+                    } else {
+                        current.push(c);
+                    }
+                }
+            }
+            rest = &rest[end..];
+        }
+    }
+    flush(&mut paragraphs, &mut current, heading);
+    paragraphs
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let c = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            "euro" => Some('€'),
+            "copy" => Some('©'),
+            "reg" => Some('®'),
+            "auml" => Some('ä'),
+            "ouml" => Some('ö'),
+            "uuml" => Some('ü'),
+            "Auml" => Some('Ä'),
+            "Ouml" => Some('Ö'),
+            "Uuml" => Some('Ü'),
+            "szlig" => Some('ß'),
+            "ndash" => Some('–'),
+            "mdash" => Some('—'),
+            "hellip" => Some('…'),
+            "bull" => Some('•'),
+            numeric => {
+                let number = numeric.strip_prefix('#')?;
+                let code = match number.strip_prefix(|c: char| c == 'x' || c == 'X') {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => number.parse::<u32>().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+        match (c, entity) {
+            (Some(c), Some(entity)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::constants::DIN_A4;
+    use super::*;
+
+    #[test]
+    fn html_paragraphs() {
+        let paragraphs = html(
+            r#"<html><head><title>Shop</title><style>p { color: red }</style></head>
+<body><h1>Order  confirmation</h1><!-- tracking -->
+<p>Thank you for your order.<br>Total: 12,50&nbsp;&euro;</p>
+<ul><li>Book</li><li>Pen &amp; paper</li></ul>
+<table><tr><td>Shipping</td><td>0,00 &#8364;</td></tr></table></body></html>"#,
+        );
+        assert_eq!(
+            paragraphs,
+            vec![
+                Paragraph::new(Style::Bold, "Order confirmation"),
+                Paragraph::new(Style::Regular, "Thank you for your order."),
+                Paragraph::new(Style::Regular, "Total: 12,50\u{a0}€"),
+                Paragraph::new(Style::Regular, "• Book"),
+                Paragraph::new(Style::Regular, "• Pen & paper"),
+                Paragraph::new(Style::Regular, "Shipping   0,00 €"),
+            ]
+        );
+    }
+
+    #[test]
+    fn multipart_email() {
+        let message = b"From: Shop <orders@shop.example>\r\n\
+Subject: =?utf-8?Q?Bestellbest=C3=A4tigung?= =?utf-8?B?IE5yLiA0Mg==?=\r\n\
+Content-Type: multipart/alternative;\r\n boundary=\"xyz\"\r\n\
+\r\n\
+--xyz\r\n\
+Content-Type: text/html; charset=utf-8\r\n\
+\r\n\
+<p>html</p>\r\n\
+--xyz\r\n\
+Content-Type: text/plain; charset=utf-8\r\n\
+Content-Transfer-Encoding: quoted-printable\r\n\
+\r\n\
+Summe: 5 =E2=82=AC, vielen =\r\nDank\r\n\
+--xyz--\r\n";
+        let paragraphs = email(message).unwrap();
+        assert_eq!(
+            paragraphs,
+            vec![
+                Paragraph::new(Style::Regular, "From: Shop <orders@shop.example>"),
+                Paragraph::new(Style::Bold, "Subject: Bestellbestätigung Nr. 42"),
+                Paragraph::new(Style::Regular, ""),
+                Paragraph::new(Style::Mono, "Summe: 5 €, vielen Dank"),
+            ]
+        );
+    }
+
+    #[test]
+    fn charsets() {
+        let bytes = b"5 \x80 \x93quoted\x94 \xa4";
+        assert_eq!(
+            decode_charset(bytes, Some("windows-1252")),
+            "5 € “quoted” ¤"
+        );
+        assert_eq!(decode_charset(b"5 \xa4 \xbd", Some("ISO-8859-15")), "5 € œ");
+        assert_eq!(decode_charset(b"5 \xa4", Some("iso-8859-1")), "5 ¤");
+    }
+
+    #[test]
+    fn wrapping() {
+        let width = text_width("0123456789", TTF_MONO, 10).unwrap();
+        let lines = wrap(
+            "  indented words and averyveryverylongword",
+            TTF_MONO,
+            10,
+            width,
+        )
+        .unwrap();
+        assert!(lines
+            .iter()
+            .all(|line| text_width(line, TTF_MONO, 10).unwrap() <= width));
+        assert!(lines[0].starts_with("  "));
+        assert_eq!(
+            lines.concat().replace(' ', ""),
+            "indentedwordsandaveryveryverylongword"
+        );
+        assert_eq!(wrap("", TTF_MONO, 10, width).unwrap(), vec![String::new()]);
+    }
+
+    #[test]
+    fn email_receipt() {
+        let message = b"From: Shop <orders@shop.example>\r\n\
+Subject: Order 42\r\n\
+Content-Type: text/plain; charset=utf-8\r\n\
+\r\n\
+Order  42\r\n\
+\r\n\
+Total:  5,00 EUR\r\n";
+        let document = text_receipt(message, TextKind::Email, DIN_A4).unwrap();
+        assert_eq!(document.get_pages().len(), 1);
+    }
+}
@@ -1,8 +1,36 @@
-pub use printpdf::{Color, IndirectFontRef, Mm, Pt, Px};
+pub use printpdf::{Color, IndirectFontRef, Mm, Point, Pt, Px};
 
-pub struct Dimensions {
-    pub height: Mm,
-    pub width: Mm,
+/// An axis aligned rectangle.
+#[derive(Debug, Clone, Copy)]
+pub struct Rect {
+    pub lower_left: Point,
+    pub upper_right: Point,
+}
+
+impl Rect {
+    /// Create a rectangle from two arbitrary corners.
+    pub fn from_corners(a: Point, b: Point) -> Self {
+        Self {
+            lower_left: Point {
+                x: if a.x < b.x { a.x } else { b.x },
+                y: if a.y < b.y { a.y } else { b.y },
+            },
+            upper_right: Point {
+                x: if a.x < b.x { b.x } else { a.x },
+                y: if a.y < b.y { b.y } else { a.y },
+            },
+        }
+    }
+
+    /// Representation as used for `Rect` entries of pdf dictionaries.
+    pub fn to_pdf_array(self) -> Vec<lopdf::Object> {
+        vec![
+            self.lower_left.x.0.into(),
+            self.lower_left.y.0.into(),
+            self.upper_right.x.0.into(),
+            self.upper_right.y.0.into(),
+        ]
+    }
 }
 
 pub use iban::Iban;
@@ -0,0 +1,348 @@
+//! SEPA credit transfer initiation (pain.001) for the employer to pay out
+//! the reimbursement.
+
+use crate::errors::*;
+use crate::types::{BankInfo, Euro};
+
+use iban::{Iban, IbanLike};
+use serde::Deserialize;
+
+use std::fmt::Write;
+
+const MAX_NAME: usize = 70;
+const MAX_REFERENCE: usize = 140;
+const MAX_IDENTIFIER: usize = 35;
+
+/// Schema version of the generated file.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PainVersion {
+    /// pain.001.001.03, accepted by all SEPA banks.
+    #[default]
+    #[serde(rename = "03")]
+    V03,
+    /// pain.001.001.09, the 2019 rulebook version.
+    #[serde(rename = "09")]
+    V09,
+}
+
+impl PainVersion {
+    fn namespace(self) -> &'static str {
+        match self {
+            PainVersion::V03 => "urn:iso:std:iso:20022:tech:xsd:pain.001.001.03",
+            PainVersion::V09 => "urn:iso:std:iso:20022:tech:xsd:pain.001.001.09",
+        }
+    }
+}
+
+/// Account of the company paying the reimbursements.
+#[derive(Debug, Clone)]
+pub struct Debtor {
+    pub name: String,
+    pub iban: Iban,
+    pub bic: Option<String>,
+}
+
+/// A single reimbursement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transfer {
+    /// Identifies the transfer end to end, the report identifier.
+    pub end_to_end_id: String,
+    pub name: String,
+    /// IBAN in electronic format, without spaces.
+    pub iban: String,
+    pub bic: Option<String>,
+    pub amount: Euro,
+    /// Unstructured remittance information.
+    pub reference: String,
+}
+
+impl Transfer {
+    pub fn new(
+        report_id: &str,
+        bankinfo: &BankInfo,
+        amount: Euro,
+        reference: impl AsRef<str>,
+    ) -> Result<Self> {
+        if !(0.01..=999_999_999.99).contains(&amount.0) {
+            bail!("Amount {} € can not be transferred", amount);
+        }
+        Ok(Self {
+            end_to_end_id: identifier(report_id),
+            name: bankinfo.name.chars().take(MAX_NAME).collect(),
+            iban: bankinfo.iban.electronic_str().to_owned(),
+            bic: bankinfo.bic(),
+            amount,
+            reference: reference.as_ref().chars().take(MAX_REFERENCE).collect(),
+        })
+    }
+}
+
+/// One payment of the debtor, with one transfer per report.
+#[derive(Debug, Clone)]
+pub struct CreditTransfer {
+    pub version: PainVersion,
+    pub debtor: Debtor,
+    pub execution_date: chrono::NaiveDate,
+    pub transfers: Vec<Transfer>,
+}
+
+impl CreditTransfer {
+    /// Add `transfer`, replacing one with the same end to end identifier.
+    pub fn add(&mut self, transfer: Transfer) {
+        self.transfers
+            .retain(|existing| existing.end_to_end_id != transfer.end_to_end_id);
+        self.transfers.push(transfer);
+    }
+
+    /// Read back the transfers of a file previously written by [`to_xml`](Self::to_xml),
+    /// which must have been issued by the same debtor.
+    pub fn load_transfers(&mut self, xml: &str) -> Result<()> {
+        let document = roxmltree::Document::parse(xml)?;
+        let child_text = |node: roxmltree::Node, path: &[&str]| -> Option<String> {
+            let mut node = node;
+            for name in path {
+                node = node.children().find(|child| child.has_tag_name(*name))?;
+            }
+            node.text().map(|text| text.trim().to_owned())
+        };
+
+        for debtor in document
+            .descendants()
+            .filter(|node| node.has_tag_name("DbtrAcct"))
+        {
+            let iban = child_text(debtor, &["Id", "IBAN"]).unwrap_or_default();
+            if iban != self.debtor.iban.electronic_str() {
+                bail!(
+                    "Existing credit transfer is issued from {} instead of {}",
+                    iban,
+                    self.debtor.iban.electronic_str()
+                );
+            }
+        }
+
+        for node in document
+            .descendants()
+            .filter(|node| node.has_tag_name("CdtTrfTxInf"))
+        {
+            let missing = |what: &str| eyre!("Existing credit transfer lacks the {}", what);
+            let amount = child_text(node, &["Amt", "InstdAmt"])
+                .ok_or_else(|| missing("amount"))?
+                .parse::<f64>()?;
+            let bic = child_text(node, &["CdtrAgt", "FinInstnId", "BIC"])
+                .or_else(|| child_text(node, &["CdtrAgt", "FinInstnId", "BICFI"]));
+            self.add(Transfer {
+                end_to_end_id: child_text(node, &["PmtId", "EndToEndId"])
+                    .ok_or_else(|| missing("end to end identifier"))?,
+                name: child_text(node, &["Cdtr", "Nm"]).ok_or_else(|| missing("creditor"))?,
+                iban: child_text(node, &["CdtrAcct", "Id", "IBAN"])
+                    .ok_or_else(|| missing("creditor account"))?,
+                bic,
+                amount: Euro(amount),
+                reference: child_text(node, &["RmtInf", "Ustrd"]).unwrap_or_default(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn to_xml(&self) -> String {
+        let now = chrono::Local::now();
+        let message_id = identifier(&format!("SHINYPENNY-{}", now.format("%Y%m%d%H%M%S")));
+        let count = self.transfers.len();
+        let sum = Euro(
+            self.transfers
+                .iter()
+                .map(|transfer| transfer.amount.0)
+                .sum(),
+        );
+
+        let mut xml = String::new();
+        let mut line = |indent: usize, content: String| {
+            let _ = writeln!(xml, "{:indent$}{}", "", content, indent = indent * 2);
+        };
+        line(0, r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_owned());
+        line(
+            0,
+            format!(
+                r#"<Document xmlns="{}" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">"#,
+                self.version.namespace()
+            ),
+        );
+        line(1, "<CstmrCdtTrfInitn>".to_owned());
+        line(2, "<GrpHdr>".to_owned());
+        line(3, element("MsgId", &message_id));
+        line(
+            3,
+            element("CreDtTm", &now.format("%Y-%m-%dT%H:%M:%S").to_string()),
+        );
+        line(3, element("NbOfTxs", &count.to_string()));
+        line(3, element("CtrlSum", &sum.to_string()));
+        line(
+            3,
+            format!("<InitgPty>{}</InitgPty>", name(&self.debtor.name)),
+        );
+        line(2, "</GrpHdr>".to_owned());
+
+        line(2, "<PmtInf>".to_owned());
+        line(3, element("PmtInfId", &message_id));
+        line(3, element("PmtMtd", "TRF"));
+        line(3, element("BtchBookg", "true"));
+        line(3, element("NbOfTxs", &count.to_string()));
+        line(3, element("CtrlSum", &sum.to_string()));
+        line(
+            3,
+            "<PmtTpInf><SvcLvl><Cd>SEPA</Cd></SvcLvl></PmtTpInf>".to_owned(),
+        );
+        let date = self.execution_date.format("%Y-%m-%d").to_string();
+        line(
+            3,
+            match self.version {
+                PainVersion::V03 => element("ReqdExctnDt", &date),
+                PainVersion::V09 => format!("<ReqdExctnDt>{}</ReqdExctnDt>", element("Dt", &date)),
+            },
+        );
+        line(3, format!("<Dbtr>{}</Dbtr>", name(&self.debtor.name)));
+        line(3, account("DbtrAcct", self.debtor.iban.electronic_str()));
+        line(
+            3,
+            agent("DbtrAgt", self.version, self.debtor.bic.as_deref()).unwrap_or_else(|| {
+                "<DbtrAgt><FinInstnId><Othr><Id>NOTPROVIDED</Id></Othr></FinInstnId></DbtrAgt>"
+                    .to_owned()
+            }),
+        );
+        line(3, element("ChrgBr", "SLEV"));
+        for transfer in self.transfers.iter() {
+            line(3, "<CdtTrfTxInf>".to_owned());
+            line(
+                4,
+                format!(
+                    "<PmtId>{}</PmtId>",
+                    element("EndToEndId", &transfer.end_to_end_id)
+                ),
+            );
+            line(
+                4,
+                format!(
+                    r#"<Amt><InstdAmt Ccy="EUR">{}</InstdAmt></Amt>"#,
+                    transfer.amount
+                ),
+            );
+            if let Some(agent) = agent("CdtrAgt", self.version, transfer.bic.as_deref()) {
+                line(4, agent);
+            }
+            line(4, format!("<Cdtr>{}</Cdtr>", name(&transfer.name)));
+            line(4, account("CdtrAcct", &transfer.iban));
+            if !transfer.reference.is_empty() {
+                line(
+                    4,
+                    format!("<RmtInf>{}</RmtInf>", element("Ustrd", &transfer.reference)),
+                );
+            }
+            line(3, "</CdtTrfTxInf>".to_owned());
+        }
+        line(2, "</PmtInf>".to_owned());
+        line(1, "</CstmrCdtTrfInitn>".to_owned());
+        line(0, "</Document>".to_owned());
+        xml
+    }
+}
+
+/// Restrict `id` to the characters and length allowed for identifiers,
+/// keeping the end which usually carries the date.
+fn identifier(id: &str) -> String {
+    let id = id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<Vec<_>>();
+    id[id.len().saturating_sub(MAX_IDENTIFIER)..]
+        .iter()
+        .collect::<String>()
+        .trim_start_matches('-')
+        .to_owned()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn element(tag: &str, text: &str) -> String {
+    format!("<{tag}>{}</{tag}>", escape(text), tag = tag)
+}
+
+fn name(name: &str) -> String {
+    element("Nm", &name.chars().take(MAX_NAME).collect::<String>())
+}
+
+fn account(tag: &str, iban: &str) -> String {
+    format!(
+        "<{tag}><Id>{}</Id></{tag}>",
+        element("IBAN", iban),
+        tag = tag
+    )
+}
+
+/// Financial institution by BIC, the element was renamed in version 09.
+fn agent(tag: &str, version: PainVersion, bic: Option<&str>) -> Option<String> {
+    let bic = bic.filter(|bic| !bic.is_empty())?;
+    let bic = match version {
+        PainVersion::V03 => element("BIC", bic),
+        PainVersion::V09 => element("BICFI", bic),
+    };
+    Some(format!(
+        "<{tag}><FinInstnId>{}</FinInstnId></{tag}>",
+        bic,
+        tag = tag
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn credit_transfer(version: PainVersion) -> CreditTransfer {
+        CreditTransfer {
+            version,
+            debtor: Debtor {
+                name: "Big S Corp".to_owned(),
+                iban: Iban::from_str("DE02120300000000202051").unwrap(),
+                bic: Some("BYLADEM1001".to_owned()),
+            },
+            execution_date: chrono::NaiveDate::from_ymd(2026, 10, 19),
+            transfers: vec![Transfer {
+                end_to_end_id: identifier("roger_ronjason__2026_10_18"),
+                name: "Roger & Ronjason".to_owned(),
+                iban: "DE89370400440532013000".to_owned(),
+                bic: None,
+                amount: Euro(118.9),
+                reference: "Reimbursement".to_owned(),
+            }],
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        for version in [PainVersion::V03, PainVersion::V09] {
+            let original = credit_transfer(version);
+            let xml = original.to_xml();
+            assert!(xml.contains("<Nm>Roger &amp; Ronjason</Nm>"));
+            assert!(xml.contains("<EndToEndId>roger-ronjason--2026-10-18</EndToEndId>"));
+            assert!(xml.contains(r#"<InstdAmt Ccy="EUR">118.90</InstdAmt>"#));
+
+            let mut batch = credit_transfer(version);
+            batch.transfers[0].end_to_end_id = "other".to_owned();
+            batch.load_transfers(&xml).unwrap();
+            assert_eq!(batch.transfers.len(), 2);
+            assert_eq!(batch.transfers[1], original.transfers[0]);
+        }
+    }
+}
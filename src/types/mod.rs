@@ -33,6 +33,8 @@ pub use self::record::*;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use float_cmp::ApproxEq;
+    use std::str::FromStr;
 
     #[test]
     fn mpy() {
@@ -47,6 +49,7 @@ mod tests {
     fn total_acc() {
         let date = chrono::Local::today();
         let r1 = Row {
+            number: 1,
             date,
             company: "Dodo GmbH".to_owned(),
             description: "Birdy".to_owned(),
@@ -57,6 +60,7 @@ mod tests {
             },
         };
         let r2 = Row {
+            number: 2,
             date,
             company: "Cuba Corp".to_owned(),
             description: "Crops".to_owned(),
@@ -67,6 +71,7 @@ mod tests {
             },
         };
         let r3 = Row {
+            number: 3,
             date,
             company: "Octopus Inc".to_owned(),
             description: "Ink".to_owned(),
@@ -77,6 +82,7 @@ mod tests {
             },
         };
         let r4 = Row {
+            number: 4,
             date,
             company: "Cuba Corp".to_owned(),
             description: "Crops (Moar)".to_owned(),
@@ -100,6 +106,7 @@ mod tests {
         assert_eq!(iter.next(), Some("".to_owned()));
         assert_eq!(iter.next(), Some("".to_owned()));
         assert_eq!(iter.next(), Some("".to_owned()));
+        assert_eq!(iter.next(), Some("".to_owned()));
         assert_eq!(iter.next(), Some("€ 41.00".to_owned()));
         assert_eq!(iter.next(), Some("0.00".to_owned()));
         assert_eq!(iter.next(), Some("1.00".to_owned()));
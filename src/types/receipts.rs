@@ -1,9 +1,181 @@
 use super::*;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// An inclusive range of one based page numbers, open ended without `last`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct PageRange {
+    pub first: usize,
+    pub last: Option<usize>,
+}
+
+impl PageRange {
+    pub fn contains(&self, page: usize) -> bool {
+        page >= self.first && self.last.is_none_or(|last| page <= last)
+    }
+}
+
+impl FromStr for PageRange {
+    type Err = Error;
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        let number = |s: &str| -> Result<usize> {
+            match s.trim().parse::<usize>() {
+                Ok(0) | Err(_) => bail!("Page numbers start at 1, got `{}`", s),
+                Ok(number) => Ok(number),
+            }
+        };
+        let range = if let Some((first, last)) = s.split_once('-') {
+            Self {
+                first: number(first)?,
+                last: if last.trim().is_empty() {
+                    None
+                } else {
+                    Some(number(last)?)
+                },
+            }
+        } else {
+            let page = number(s)?;
+            Self {
+                first: page,
+                last: Some(page),
+            }
+        };
+        if matches!(range.last, Some(last) if last < range.first) {
+            bail!("Page range `{}` is reversed", s);
+        }
+        Ok(range)
+    }
+}
+
+impl fmt::Display for PageRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.last {
+            Some(last) if last == self.first => write!(f, "{}", self.first),
+            Some(last) => write!(f, "{}-{}", self.first, last),
+            None => write!(f, "{}-", self.first),
+        }
+    }
+}
+
+/// A receipt file, optionally reduced to some of its pages and rotated,
+/// i.e. `hotel.pdf#pages=2-3;rotate=90`, or a member of an archive, i.e.
+/// `trip.zip!/taxi.pdf`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ReceiptSpec {
+    pub path: PathBuf,
+    /// Member of the archive at `path`.
+    pub member: Option<PathBuf>,
+    /// Pages to keep, all if empty. Given as `pages=1+3-4` or `pages=2-`.
+    pub pages: Vec<PageRange>,
+    /// Clockwise rotation in degrees, a multiple of 90.
+    pub rotate: u16,
+}
+
+impl From<PathBuf> for ReceiptSpec {
+    fn from(path: PathBuf) -> Self {
+        Self {
+            path,
+            member: None,
+            pages: Vec::new(),
+            rotate: 0,
+        }
+    }
+}
+
+impl fmt::Display for ReceiptSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(ref member) = self.member {
+            write!(f, "!/{}", member.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl ReceiptSpec {
+    /// Expand a directory or an archive without a member into all the
+    /// receipts within, sorted by name, keeping the options.
+    pub fn expand(self) -> Result<Vec<Self>> {
+        if self.path.is_dir() {
+            let files = crate::archive::directory_receipts(&self.path)?;
+            if files.is_empty() {
+                bail!("Directory {} contains no receipts", self.path.display());
+            }
+            Ok(files
+                .into_iter()
+                .map(|path| Self {
+                    path,
+                    ..self.clone()
+                })
+                .collect())
+        } else if self.member.is_none() && crate::archive::is_archive(&self.path) {
+            let members = crate::archive::archive_receipts(&self.path)?;
+            if members.is_empty() {
+                bail!("Archive {} contains no receipts", self.path.display());
+            }
+            Ok(members
+                .into_iter()
+                .map(|member| Self {
+                    member: Some(member),
+                    ..self.clone()
+                })
+                .collect())
+        } else {
+            Ok(vec![self])
+        }
+    }
+}
+
+impl FromStr for ReceiptSpec {
+    type Err = Error;
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        // a `#` might as well be part of the file name
+        let (path, options) = match s.rsplit_once('#') {
+            Some((path, options))
+                if options.starts_with("pages=") || options.starts_with("rotate=") =>
+            {
+                (path, options)
+            }
+            _ => (s, ""),
+        };
+        let mut spec = if let Some((archive, member)) = path.split_once("!/") {
+            Self {
+                member: Some(PathBuf::from(member.trim())),
+                ..Self::from(PathBuf::from(archive.trim()))
+            }
+        } else {
+            Self::from(PathBuf::from(path.trim()))
+        };
+        for option in options
+            .split(';')
+            .filter(|option| !option.trim().is_empty())
+        {
+            match option.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+                Some(("pages", pages)) => {
+                    spec.pages = pages
+                        .split('+')
+                        .map(PageRange::from_str)
+                        .collect::<Result<Vec<_>>>()?;
+                }
+                Some(("rotate", rotate)) => {
+                    spec.rotate = match rotate.parse::<i32>() {
+                        Ok(rotate) if rotate % 90 == 0 => rotate.rem_euclid(360) as u16,
+                        _ => bail!("Rotation must be a multiple of 90, got `{}`", rotate),
+                    };
+                }
+                _ => bail!(
+                    "Unknown receipt option `{}`, expected `pages=` or `rotate=`",
+                    option
+                ),
+            }
+        }
+        Ok(spec)
+    }
+}
 
 // A set of receipts
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
-pub struct Receipts(indexmap::IndexSet<PathBuf>);
+pub struct Receipts(indexmap::IndexSet<ReceiptSpec>);
 
 impl<A> std::iter::FromIterator<A> for Receipts
 where
@@ -19,8 +191,8 @@ where
 }
 
 impl<'p> std::iter::IntoIterator for &'p Receipts {
-    type Item = &'p PathBuf;
-    type IntoIter = indexmap::set::Iter<'p, PathBuf>;
+    type Item = &'p ReceiptSpec;
+    type IntoIter = indexmap::set::Iter<'p, ReceiptSpec>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.0.iter()
@@ -32,12 +204,16 @@ where
     T: std::convert::AsRef<Path>,
 {
     fn from(bare: Vec<T>) -> Self {
-        Receipts(bare.into_iter().map(|p| p.as_ref().to_owned()).collect())
+        Receipts(
+            bare.into_iter()
+                .map(|p| ReceiptSpec::from(p.as_ref().to_owned()))
+                .collect(),
+        )
     }
 }
 
 impl Receipts {
-    pub fn insert(&mut self, x: impl Into<PathBuf>) {
+    pub fn insert(&mut self, x: impl Into<ReceiptSpec>) {
         self.0.insert(x.into());
     }
 }
@@ -48,7 +224,7 @@ impl<'de> serde::de::Visitor<'de> for ReceiptsVisitor {
     type Value = Receipts;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        write!(formatter, "Receipts must a comma delimited list of paths relative to the current work dir or absulte")
+        write!(formatter, "Receipts must a comma delimited list of paths relative to the current work dir or absulte, each optionally followed by `#pages=2-3;rotate=90`")
     }
 
     fn visit_str<E>(self, s: &str) -> std::result::Result<Self::Value, E>
@@ -58,20 +234,22 @@ impl<'de> serde::de::Visitor<'de> for ReceiptsVisitor {
         if s.is_empty() {
             Ok(Self::Value::default())
         } else {
-            let bare = s
+            let specs = s
                 .split(',')
-                .try_fold(Vec::<PathBuf>::default(), |mut acc, path_s| {
+                .try_fold(Receipts::default(), |mut acc, path_s| {
                     let path = path_s.trim();
                     if path.is_empty() {
                         return Err(serde::de::Error::custom(format!(
                             "Must contain at least one (1) receipt"
                         )));
                     } else {
-                        acc.push(PathBuf::from(path));
+                        let spec = ReceiptSpec::from_str(path)
+                            .map_err(|e| serde::de::Error::custom(format!("{}", e)))?;
+                        acc.insert(spec);
                     }
                     Ok(acc)
                 })?;
-            Ok(Self::Value::from(bare))
+            Ok(specs)
         }
     }
 }
@@ -84,3 +262,43 @@ impl<'de> serde::de::Deserialize<'de> for Receipts {
         deserializer.deserialize_str(ReceiptsVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec() {
+        let spec = ReceiptSpec::from_str("hotel.pdf#pages=2-3+5;rotate=-90").unwrap();
+        assert_eq!(spec.path, PathBuf::from("hotel.pdf"));
+        assert_eq!(
+            spec.pages,
+            vec![
+                PageRange {
+                    first: 2,
+                    last: Some(3)
+                },
+                PageRange {
+                    first: 5,
+                    last: Some(5)
+                }
+            ]
+        );
+        assert_eq!(spec.rotate, 270);
+
+        let spec = ReceiptSpec::from_str("trip.zip!/taxi/2021-03.pdf#rotate=180").unwrap();
+        assert_eq!(spec.path, PathBuf::from("trip.zip"));
+        assert_eq!(spec.member, Some(PathBuf::from("taxi/2021-03.pdf")));
+        assert_eq!(spec.rotate, 180);
+        assert_eq!(spec.to_string(), "trip.zip!/taxi/2021-03.pdf");
+
+        let spec = ReceiptSpec::from_str("scans/#42.png").unwrap();
+        assert_eq!(spec.path, PathBuf::from("scans/#42.png"));
+        assert!(spec.pages.is_empty());
+
+        assert!(ReceiptSpec::from_str("a.pdf#pages=3-2").is_err());
+        assert!(ReceiptSpec::from_str("a.pdf#pages=0").is_err());
+        assert!(ReceiptSpec::from_str("a.pdf#rotate=45").is_err());
+        assert!(ReceiptSpec::from_str("a.pdf#pages=2;scale=2").is_err());
+    }
+}
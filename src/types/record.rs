@@ -18,6 +18,8 @@ pub struct Record {
 /// A table row to be displayed in the pdf table.
 #[derive(Debug, Clone)]
 pub struct Row {
+    /// One based position within the table, also used to reference the receipts.
+    pub number: usize,
     pub date: Date,
     pub description: String,
     pub company: String,
@@ -31,6 +33,32 @@ impl Row {
     pub fn iter(&self) -> RowCellIter {
         RowCellIter::new(&self)
     }
+
+    /// Machine readable representation, amounts are given in €.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "number": self.number,
+            "date": self.date.format("%Y-%m-%d").to_string(),
+            "company": self.company,
+            "description": self.description,
+            "currency": self.brutto.currency().code(),
+            "exchange_rate": self.brutto.exchange_rate(),
+            "netto": self.netto.as_euro().to_string(),
+            "tax": tax_json(&self.tax_total),
+            "brutto": self.brutto.as_euro().to_string(),
+        })
+    }
+
+    /// Short reference to the row, to be stamped onto the receipts.
+    pub fn reference(&self) -> String {
+        format!(
+            "#{:02} · {} · {} · {} €",
+            self.number,
+            self.date.format("%Y-%m-%d"),
+            self.company,
+            self.brutto.as_euro()
+        )
+    }
 }
 
 impl<'a> IntoIterator for &'a Row {
@@ -59,18 +87,19 @@ impl<'a> Iterator for RowCellIter<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         let tax_classes = self.row.tax_total.len();
         let val = match self.idx {
-            0 => self.row.date.format("%Y-%m-%d").to_string(),
-            1 => self.row.description.clone(),
-            2 => self.row.company.clone(),
-            3 => self.row.netto.to_string(),
-            x if x < (4 + tax_classes) => self
+            0 => format!("{:02}", self.row.number),
+            1 => self.row.date.format("%Y-%m-%d").to_string(),
+            2 => self.row.description.clone(),
+            3 => self.row.company.clone(),
+            4 => self.row.netto.to_string(),
+            x if x < (5 + tax_classes) => self
                 .row
                 .tax_total
-                .get_index(x.saturating_sub(4))
+                .get_index(x.saturating_sub(5))
                 .expect("Bounds are evaled outside. qed")
                 .1
                 .to_string(),
-            x if x == (4 + tax_classes) => self.row.brutto.to_string(),
+            x if x == (5 + tax_classes) => self.row.brutto.to_string(),
             _ => return None,
         };
         self.idx += 1;
@@ -87,7 +116,24 @@ pub struct Totals {
 
 use itertools::Itertools;
 
+fn tax_json(tax_total: &indexmap::IndexMap<Percentage, Euro>) -> serde_json::Value {
+    tax_total
+        .iter()
+        .map(|(percentage, value)| (percentage.to_string(), value.to_string().into()))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
 impl Totals {
+    /// Machine readable representation, amounts are given in €.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "netto": self.netto.to_string(),
+            "tax": tax_json(&self.tax_total),
+            "brutto": self.brutto.to_string(),
+        })
+    }
+
     pub fn add(&mut self, other: &Row) {
         self.brutto += other.brutto.as_euro();
         self.netto += other.netto.as_euro();
@@ -137,10 +183,10 @@ impl<'a> Iterator for TotalCellIter<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         let tax_classes = self.total.tax_total.len();
         let val = match self.idx {
-            0 | 1 | 2 => "".to_owned(),
-            3 => format!("€ {}", self.total.netto),
-            x if x < (4 + tax_classes) => self.sorted[x.saturating_sub(4)].to_string(),
-            x if x == 4 + tax_classes => self.total.brutto.to_string(),
+            0..=3 => "".to_owned(),
+            4 => format!("€ {}", self.total.netto),
+            x if x < (5 + tax_classes) => self.sorted[x.saturating_sub(5)].to_string(),
+            x if x == 5 + tax_classes => self.total.brutto.to_string(),
             _ => return None,
         };
         self.idx += 1;
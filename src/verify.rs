@@ -0,0 +1,157 @@
+//! Cross-check of the amounts and dates of records against the text of
+//! their receipts, to catch typos in the csv.
+//!
+//! Values are searched in the common notations, i.e. `1.234,50` and
+//! `1,234.50` or `2021-03-04` and `04.03.2021`.
+
+use crate::types::Record;
+
+const GERMAN_MONTHS: &[&str] = &[
+    "januar",
+    "februar",
+    "märz",
+    "april",
+    "mai",
+    "juni",
+    "juli",
+    "august",
+    "september",
+    "oktober",
+    "november",
+    "dezember",
+];
+
+/// Notations of `amount`, always with two decimals.
+pub fn amount_notations(amount: f64) -> Vec<String> {
+    let cents = (amount * 100.).round() as i64;
+    let (whole, fraction) = (cents / 100, cents % 100);
+    let grouped = |separator: &str| {
+        let digits = whole.to_string();
+        let mut grouped = String::new();
+        for (idx, digit) in digits.chars().enumerate() {
+            if idx > 0 && (digits.len() - idx) % 3 == 0 {
+                grouped.push_str(separator);
+            }
+            grouped.push(digit);
+        }
+        grouped
+    };
+    let mut notations = vec![
+        format!("{}.{:02}", whole, fraction),
+        format!("{},{:02}", whole, fraction),
+    ];
+    if whole >= 1000 {
+        notations.push(format!("{}.{:02}", grouped(","), fraction));
+        notations.push(format!("{},{:02}", grouped("."), fraction));
+        notations.push(format!("{},{:02}", grouped(" "), fraction));
+        notations.push(format!("{}.{:02}", grouped("'"), fraction));
+    }
+    notations
+}
+
+/// Notations of `date`, numeric ones and with english or german month names.
+pub fn date_notations(date: chrono::NaiveDate) -> Vec<String> {
+    use chrono::Datelike;
+
+    let mut notations = [
+        "%Y-%m-%d",
+        "%d.%m.%Y",
+        "%-d.%-m.%Y",
+        "%d.%m.%y",
+        "%d/%m/%Y",
+        "%m/%d/%Y",
+        "%d-%m-%Y",
+        "%Y/%m/%d",
+        "%-d %B %Y",
+        "%-d. %B %Y",
+        "%B %-d, %Y",
+        "%d %b %Y",
+        "%b %-d, %Y",
+    ]
+    .iter()
+    .map(|format| date.format(format).to_string().to_lowercase())
+    .collect::<Vec<_>>();
+    let month = GERMAN_MONTHS[date.month0() as usize];
+    notations.push(format!("{}. {} {}", date.day(), month, date.year()));
+    notations.push(format!("{:02}. {} {}", date.day(), month, date.year()));
+    notations
+}
+
+/// Lower case `text` with all kinds of whitespace collapsed to single spaces.
+fn normalize(text: &str) -> String {
+    text.split(|c: char| c.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Whether `text` contains `needle` not as part of a longer number.
+fn contains_value(text: &str, needle: &str) -> bool {
+    text.match_indices(needle).any(|(idx, _)| {
+        let before = text[..idx].chars().rev();
+        let after = text[idx + needle.len()..].chars();
+        !continues_number(before) && !continues_number(after)
+    })
+}
+
+/// Whether the characters next to a value continue it as a number, a
+/// separator only does if followed by a digit.
+fn continues_number(mut chars: impl Iterator<Item = char>) -> bool {
+    match chars.next() {
+        Some(c) if c.is_ascii_digit() => true,
+        Some('.') | Some(',') => chars.next().is_some_and(|c| c.is_ascii_digit()),
+        _ => false,
+    }
+}
+
+/// Whether any of `notations` is found on any of `pages`.
+///
+/// Text extracted from pdfs often has spurious spaces between glyphs,
+/// so the pages are searched without any spaces as well.
+fn found(pages: &[String], notations: &[String]) -> bool {
+    pages.iter().any(|page| {
+        let page = normalize(page);
+        let compact = page.replace(' ', "");
+        notations.iter().any(|notation| {
+            contains_value(&page, notation) || contains_value(&compact, &notation.replace(' ', ""))
+        })
+    })
+}
+
+/// The values of `record` missing on the text of its receipt `pages`,
+/// the date is only looked for if `dates` is set.
+pub fn missing_values(record: &Record, pages: &[String], dates: bool) -> Vec<String> {
+    let mut missing = Vec::new();
+    if !found(pages, &amount_notations(record.brutto.amount())) {
+        missing.push(format!("brutto {}", record.brutto));
+    }
+    if dates && !found(pages, &date_notations(record.date)) {
+        missing.push(format!("date {}", record.date.format("%Y-%m-%d")));
+    }
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notations() {
+        let amounts = amount_notations(1234.5);
+        assert!(amounts.contains(&"1.234,50".to_owned()));
+        assert!(amounts.contains(&"1,234.50".to_owned()));
+        let page = ["Summe   EUR 1.234,50\nMwSt 19 %".to_owned()];
+        assert!(found(&page, &amounts));
+        assert!(!found(&page, &amount_notations(234.5)));
+        assert!(!found(&page, &amount_notations(1.23)));
+
+        let date = chrono::NaiveDate::from_ymd(2021, 3, 4);
+        let pages = ["Rechnungsdatum: 4. März 2021".to_owned()];
+        assert!(found(&pages, &date_notations(date)));
+        let pages = ["Date 0 4 . 0 3 . 2 0 2 1".to_owned()];
+        assert!(found(&pages, &date_notations(date)));
+        let pages = ["14.03.2021".to_owned()];
+        assert!(!found(&pages, &date_notations(date)));
+    }
+}